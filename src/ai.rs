@@ -2,122 +2,972 @@ extern crate glui;
 extern crate glui_proc;
 extern crate rand;
 extern crate serde_json;
-use rand::seq::SliceRandom;
-use rand::thread_rng;
-use std::cell::RefCell;
-use std::collections::HashMap;
 // use tools::*;
 
 use super::board::*;
+use super::book::Book;
+use super::profile::*;
+use super::shapes::*;
 use std::cmp::*;
-
+use std::fmt;
+use std::sync::atomic::AtomicU64;
+use std::sync::atomic::Ordering::Relaxed;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+mod mcts;
+mod tss;
+use self::mcts::mcts_move;
+pub use self::mcts::{MctsOptions, PlayoutPolicy};
+use self::tss::ThreatSearch;
+
+#[derive(Clone)]
 struct AiData {
     white_board: BoardAsNums,
     black_board: BoardAsNums,
+    level: Level,
+    profile: Profile,
+    /// Zobrist hash of the stones on the board.
+    hash: u64,
+    /// Shared by all the search threads.
+    table: Arc<TransTable>,
+    /// Two moves per number of stones on the board that recently caused a cutoff there.
+    killers: Vec<[Option<(usize, usize)>; 2]>,
+    /// How much searching each intersection caused cutoffs during the current move's search.
+    history: [[u32; MAP_SIZE]; MAP_SIZE],
+    /// The number of threads searching, each with its own copy of this.
+    threads: usize,
+    /// Picks the game's random numbers, `rng` is seeded from it and the position.
+    seed: u64,
+    /// Breaks ties and makes the mistakes of the weaker levels.
+    rng: Random,
 }
 
 impl AiData {
     fn new() -> AiData {
-        AiData {
-            white_board: BoardAsNums::new(Cell::White),
-            black_board: BoardAsNums::new(Cell::Black),
+        let shapes = Shapes::default();
+        let mut ai_data = AiData {
+            white_board: BoardAsNums::new(Cell::White, &shapes),
+            black_board: BoardAsNums::new(Cell::Black, &shapes),
+            level: LEVELS[DEFAULT_LEVEL],
+            profile: Profile::default(),
+            hash: 0,
+            table: Arc::new(TransTable::new(TABLE_SIZE)),
+            killers: vec![[None; 2]; MAP_SIZE * MAP_SIZE + 1],
+            history: [[0; MAP_SIZE]; MAP_SIZE],
+            threads: thread::available_parallelism().map_or(1, |n| n.get()),
+            seed: 0,
+            rng: Random(0),
+        };
+        ai_data.set_profile(Profile::default());
+        ai_data
+    }
+    fn set_profile(&mut self, profile: Profile) {
+        for board in [&mut self.white_board, &mut self.black_board].iter_mut() {
+            board.weights = profile.weights;
+            board.forks = profile.forks;
+        }
+        self.profile = profile;
+    }
+    fn next_color(&self) -> Cell {
+        if self.white_board.moves.len() % 2 == 0 {
+            Cell::Black
+        } else {
+            Cell::White
+        }
+    }
+    fn put(&mut self, x: usize, y: usize) {
+        self.hash ^= zobrist(x, y, self.next_color());
+        self.white_board.put(x, y);
+        self.black_board.put(x, y);
+    }
+    fn undo(&mut self) {
+        if let Some(&(x, y)) = self.white_board.moves.last() {
+            self.white_board.undo();
+            self.black_board.undo();
+            self.hash ^= zobrist(x, y, self.next_color());
+        }
+    }
+    /// The boards scoring the threats of `player` and of the opponent.
+    fn boards(&self, player: Cell) -> (&BoardAsNums, &BoardAsNums) {
+        if player == Cell::Black {
+            (&self.black_board, &self.white_board)
+        } else {
+            (&self.white_board, &self.black_board)
+        }
+    }
+    fn cutoff(&mut self, pos: (usize, usize), depth: u32) {
+        let killers = &mut self.killers[self.white_board.moves.len()];
+        if killers[0] != Some(pos) {
+            killers[1] = killers[0];
+            killers[0] = Some(pos);
         }
+        self.history[pos.0][pos.1] += depth * depth;
+    }
+    /// The key of the current position in the transposition table, values are stored
+    /// from the point of view of the searching player, so that is part of the key.
+    fn key(&self, my_turn: bool) -> u64 {
+        if my_turn == (self.next_color() == Cell::Black) {
+            self.hash ^ BLACK_SEARCHES
+        } else {
+            self.hash
+        }
+    }
+    /// Seeds `rng` for choosing a move in the current position, the same seed and position
+    /// always give the same numbers.
+    fn reseed(&mut self) {
+        self.rng = Random(mix(self.seed ^ self.hash));
     }
+    /// A copy for the `id`-th search thread, with its own random numbers. The transposition
+    /// and pattern tables are shared, so it is cheap to make.
+    fn helper(&self, id: usize) -> AiData {
+        let mut helper = self.clone();
+        helper.rng = Random(mix(self.rng.0 ^ id as u64));
+        helper
+    }
+}
+
+const GOLDEN_GAMMA: u64 = 0x9e37_79b9_7f4a_7c15;
+
+/// The output function of SplitMix64.
+fn mix(mut z: u64) -> u64 {
+    z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    z ^ (z >> 31)
+}
+
+/// A fixed random number for every stone on every intersection, generated with SplitMix64.
+/// The hash of a position is these XOR-ed together for all of its stones.
+pub fn zobrist(x: usize, y: usize, cell: Cell) -> u64 {
+    let id = ((x * MAP_SIZE + y) * 2 + (cell == Cell::White) as usize) as u64;
+    mix(id.wrapping_add(1).wrapping_mul(GOLDEN_GAMMA))
 }
 
-thread_local! {
-    static AIDATA_INSTANCE: RefCell<AiData> = RefCell::new(AiData::new());
+/// SplitMix64, the same seed always gives the same numbers.
+#[derive(Debug, Copy, Clone)]
+struct Random(u64);
+
+impl Random {
+    fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(GOLDEN_GAMMA);
+        mix(self.0)
+    }
+    /// A number in `[0, 1)`.
+    fn unit(&mut self) -> f32 {
+        (self.next() >> 40) as f32 / (1u64 << 24) as f32
+    }
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+    fn shuffle<T>(&mut self, items: &mut [T]) {
+        for i in (1..items.len()).rev() {
+            items.swap(i, self.below(i + 1));
+        }
+    }
 }
 
-#[allow(dead_code)]
-pub fn ai_eval(moves: &str, my_turn: bool) -> serde_json::Result<Vec<f32>> {
-    let moves: Vec<(usize, usize)> = serde_json::from_str(moves)?;
+const BLACK_SEARCHES: u64 = 0x2545_f491_4f6c_dd1d;
 
-    ai_new_game(&vec![]);
+/// Entries of the transposition table, 16 MB.
+const TABLE_SIZE: usize = 1 << 20;
 
-    let mut evals = vec![];
+#[derive(Debug, Copy, Clone, PartialEq)]
+enum Bound {
+    Exact,
+    /// The value is at least this much.
+    Lower,
+    /// The value is at most this much.
+    Upper,
+}
 
-    AIDATA_INSTANCE.with(|ai_data_pers| {
-        let mut ai_data = ai_data_pers.borrow_mut();
+#[derive(Debug, Copy, Clone, PartialEq)]
+struct TableEntry {
+    key: u64,
+    value: f32,
+    depth: u8,
+    bound: Bound,
+    best: (u8, u8),
+}
 
-        for p in moves {
-            ai_data.white_board.put(p.0, p.1);
-            ai_data.black_board.put(p.0, p.1);
-            let Move { value, .. } =
-                alphabeta(&mut ai_data, my_turn, 0.51, 3, std::f32::MIN, std::f32::MAX);
-            evals.push(value);
+impl TableEntry {
+    /// Everything but the key in one number: value, depth, bound and the best move from the low bits up.
+    fn pack(&self) -> u64 {
+        let bound = match self.bound {
+            Bound::Exact => 0,
+            Bound::Lower => 1,
+            Bound::Upper => 2,
+        };
+        self.value.to_bits() as u64
+            | (self.depth as u64) << 32
+            | bound << 40
+            | (self.best.0 as u64) << 48
+            | (self.best.1 as u64) << 56
+    }
+    fn unpack(key: u64, data: u64) -> TableEntry {
+        TableEntry {
+            key,
+            value: f32::from_bits(data as u32),
+            depth: (data >> 32) as u8,
+            bound: match (data >> 40) & 3 {
+                0 => Bound::Exact,
+                1 => Bound::Lower,
+                _ => Bound::Upper,
+            },
+            best: ((data >> 48) as u8, (data >> 56) as u8),
         }
-    });
+    }
+}
 
-    Ok(evals)
+/// Results of earlier searches, indexed by the position's hash. They stay valid when moves
+/// are taken back, only a change of the evaluation clears them.
+///
+/// The search threads share one table without locking: every entry is the key XOR-ed with
+/// the packed data, then the data, so an entry torn by two threads writing it at once
+/// does not match its key and is ignored. Depth is never 0, so empty entries match no key.
+struct TransTable {
+    entries: Vec<(AtomicU64, AtomicU64)>,
 }
 
-pub fn ai_new_game(moves: &Vec<(usize, usize)>) {
-    AIDATA_INSTANCE.with(|ai_data_pers| {
-        let mut ai_data = ai_data_pers.borrow_mut();
+impl TransTable {
+    fn new(size: usize) -> TransTable {
+        TransTable {
+            entries: (0..size)
+                .map(|_| (AtomicU64::new(0), AtomicU64::new(0)))
+                .collect(),
+        }
+    }
+    fn load(&self, id: usize) -> Option<TableEntry> {
+        let (check, data) = &self.entries[id];
+        let data = data.load(Relaxed);
+        if data == 0 {
+            return None;
+        }
+        Some(TableEntry::unpack(check.load(Relaxed) ^ data, data))
+    }
+    fn probe(&self, key: u64) -> Option<TableEntry> {
+        self.load(key as usize % self.entries.len())
+            .filter(|e| e.key == key)
+    }
+    fn store(&self, entry: TableEntry) {
+        let id = entry.key as usize % self.entries.len();
+        let replace = match self.load(id) {
+            Some(old) => old.key != entry.key || old.depth <= entry.depth,
+            None => true,
+        };
+        if replace {
+            let data = entry.pack();
+            self.entries[id].0.store(entry.key ^ data, Relaxed);
+            self.entries[id].1.store(data, Relaxed);
+        }
+    }
+    fn clear(&self) {
+        for (check, data) in &self.entries {
+            check.store(0, Relaxed);
+            data.store(0, Relaxed);
+        }
+    }
+}
 
-        let n = ai_data.white_board.moves.len();
-        for _ in 0..n {
-            ai_data.white_board.undo();
-            ai_data.black_board.undo();
+/// How long and how deep the AI may think about a move.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct SearchLimits {
+    /// The deepest search tried, in moves.
+    pub depth: u32,
+    /// Time per move, the deepest search finished in time is used.
+    pub time: Option<Duration>,
+    /// Positions evaluated per move.
+    pub nodes: Option<usize>,
+}
+
+impl Default for SearchLimits {
+    fn default() -> Self {
+        LEVELS[DEFAULT_LEVEL].limits
+    }
+}
+
+/// How strong the AI plays.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Level {
+    pub name: &'static str,
+    pub limits: SearchLimits,
+    /// The most that is added to or taken from the value of a move at random, an open three is worth 1.
+    pub noise: f32,
+    /// The chance of playing a random move near the stones instead of searching.
+    pub blunder: f32,
+    /// Whether forced wins are searched for, and defended against.
+    pub threat_search: bool,
+    /// Playouts per move with Monte Carlo tree search, which gets the time of `limits` too.
+    pub playouts: usize,
+}
+
+impl Level {
+    pub fn by_name(name: &str) -> Option<Level> {
+        LEVELS.iter().cloned().find(|l| l.name == name)
+    }
+}
+
+pub const LEVELS: [Level; 4] = [
+    Level {
+        name: "Beginner",
+        limits: SearchLimits {
+            depth: 1,
+            time: Some(Duration::from_millis(200)),
+            nodes: None,
+        },
+        noise: 1.0,
+        blunder: 0.2,
+        threat_search: false,
+        playouts: 300,
+    },
+    Level {
+        name: "Easy",
+        limits: SearchLimits {
+            depth: 2,
+            time: Some(Duration::from_millis(500)),
+            nodes: None,
+        },
+        noise: 0.3,
+        blunder: 0.05,
+        threat_search: false,
+        playouts: 1000,
+    },
+    Level {
+        name: "Medium",
+        limits: SearchLimits {
+            depth: 4,
+            time: Some(Duration::from_millis(1000)),
+            nodes: None,
+        },
+        noise: 0.05,
+        blunder: 0.0,
+        threat_search: true,
+        playouts: 2500,
+    },
+    Level {
+        name: "Hard",
+        limits: SearchLimits {
+            depth: 8,
+            time: Some(Duration::from_millis(3000)),
+            nodes: None,
+        },
+        noise: 0.0,
+        blunder: 0.0,
+        threat_search: true,
+        playouts: 5000,
+    },
+];
+
+pub const DEFAULT_LEVEL: usize = 2;
+
+/// Keeps track of the limits while searching, once they run out the search unwinds.
+struct Budget {
+    deadline: Option<Instant>,
+    nodes: Option<usize>,
+    searched: usize,
+    /// The limits are only enforced after the first depth, so there is always a move to play.
+    armed: bool,
+    stopped: bool,
+}
+
+impl Budget {
+    fn new(limits: &SearchLimits) -> Budget {
+        Budget {
+            deadline: limits.time.map(|t| Instant::now() + t),
+            nodes: limits.nodes,
+            searched: 0,
+            armed: false,
+            stopped: false,
+        }
+    }
+    fn unlimited() -> Budget {
+        Budget::new(&SearchLimits {
+            depth: 0,
+            time: None,
+            nodes: None,
+        })
+    }
+    fn exhausted(&mut self) -> bool {
+        if self.armed && !self.stopped {
+            self.stopped = self.nodes.map_or(false, |n| self.searched >= n)
+                || (self.searched % 64 == 0
+                    && self.deadline.map_or(false, |d| Instant::now() >= d));
         }
+        self.stopped
+    }
+}
+
+/// How an `Engine` chooses its moves.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum Method {
+    /// Threat search and `alphabeta`, as strong as the level.
+    AlphaBeta,
+    /// Monte Carlo tree search, the level is not used.
+    Mcts(MctsOptions),
+}
+
+/// Why `Engine::best_move` chose a move.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Reason {
+    Book,
+    /// A win by a chain of fours, these are its moves.
+    WinByFours(Vec<(usize, usize)>),
+    /// A win by a chain of fours and open threes.
+    WinByThreats,
+    /// A random move, one of the mistakes of the weaker levels.
+    Random,
+    /// The best move found by `alphabeta`.
+    Search,
+    /// The move winning the most playouts of Monte Carlo tree search.
+    Playouts,
+}
 
-        for p in moves {
-            ai_data.white_board.put(p.0, p.1);
-            ai_data.black_board.put(p.0, p.1);
+/// A move chosen by `Engine::best_move`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Choice {
+    pub pos: (usize, usize),
+    pub reason: Reason,
+    /// The value of the move for `alphabeta`, the winning chance for tree search.
+    pub value: f32,
+    /// The positions evaluated, or the playouts played, to find it.
+    pub searched: usize,
+}
+
+/// Like "h8 from the opening book".
+impl fmt::Display for Choice {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", point_name(self.pos))?;
+        match &self.reason {
+            Reason::Book => write!(f, " from the opening book"),
+            Reason::WinByFours(line) => {
+                write!(f, " winning by fours:")?;
+                for &p in line {
+                    write!(f, " {}", point_name(p))?;
+                }
+                Ok(())
+            }
+            Reason::WinByThreats => write!(f, " winning by threats"),
+            Reason::Random => write!(f, " at random"),
+            Reason::Search => write!(
+                f,
+                " after evaluating {} positions, value {}",
+                self.searched, self.value
+            ),
+            Reason::Playouts => write!(
+                f,
+                " after {} playouts, winning chance {}",
+                self.searched, self.value
+            ),
         }
+    }
+}
 
-        // println!("white's board: {:#?}", ai_data.white_board.cells);
-    });
+/// An AI following one game, any number of them can be used side by side.
+pub struct Engine {
+    data: AiData,
+    book: Option<Arc<Book>>,
+    method: Method,
 }
 
-pub fn ai_redo(p: (usize, usize)) {
-    AIDATA_INSTANCE.with(|ai_data_pers| {
-        let mut ai_data = ai_data_pers.borrow_mut();
+impl Engine {
+    /// An engine at the position of `board`, playing at the default level with a seed from the clock.
+    pub fn new(board: &Board) -> Engine {
+        let mut engine = Engine {
+            data: AiData::new(),
+            book: None,
+            method: Method::AlphaBeta,
+        };
+        let now = SystemTime::now().duration_since(UNIX_EPOCH);
+        engine.set_seed(now.map_or(0, |t| t.as_nanos() as u64));
+        engine.sync(board);
+        engine
+    }
+    pub fn seed(&self) -> u64 {
+        self.data.seed
+    }
+    /// Sets the seed of the random numbers choosing among equal moves and making the mistakes
+    /// of the weaker levels, best changed for every game.
+    pub fn set_seed(&mut self, seed: u64) {
+        self.data.seed = seed;
+    }
+    pub fn level(&self) -> Level {
+        self.data.level
+    }
+    pub fn set_level(&mut self, level: Level) {
+        self.data.level = level;
+    }
+    pub fn limits(&self) -> SearchLimits {
+        self.data.level.limits
+    }
+    pub fn set_limits(&mut self, limits: SearchLimits) {
+        self.data.level.limits = limits;
+    }
+    pub fn method(&self) -> Method {
+        self.method
+    }
+    pub fn set_method(&mut self, method: Method) {
+        self.method = method;
+    }
+    /// The number of threads searching for a move.
+    pub fn threads(&self) -> usize {
+        self.data.threads
+    }
+    /// Sets the number of threads searching for a move, with one thread the same position
+    /// always gets the same move for the same seed unless the time limit runs out.
+    pub fn set_threads(&mut self, threads: usize) {
+        self.data.threads = threads.max(1);
+    }
+    pub fn profile(&self) -> &Profile {
+        &self.data.profile
+    }
+    /// Changes the playing style, the weights of `profile` take the place of the ones of the shapes.
+    pub fn set_profile(&mut self, profile: &Profile) {
+        if *profile != self.data.profile {
+            self.data.set_profile(profile.clone());
+            self.data.table.clear();
+        }
+    }
+    /// Makes the evaluation count `shapes` instead of the ones in `data/shapes.txt`.
+    pub fn set_shapes(&mut self, shapes: &Shapes) {
+        let moves = self.moves().to_vec();
+        self.data.white_board = BoardAsNums::new(Cell::White, shapes);
+        self.data.black_board = BoardAsNums::new(Cell::Black, shapes);
+        for (x, y) in moves {
+            self.data.white_board.put(x, y);
+            self.data.black_board.put(x, y);
+        }
+        self.data.set_profile(self.data.profile.clone());
+        self.data.table.clear();
+    }
+    pub fn book(&self) -> Option<&Book> {
+        self.book.as_deref()
+    }
+    /// Sets the opening book played from before searching, until the game leaves it.
+    pub fn set_book(&mut self, book: Option<Arc<Book>>) {
+        self.book = book;
+    }
+    /// The threats of the player to move and of the opponent, indexed by `Threat`.
+    pub fn threats(&self) -> ([u8; THREATS], [u8; THREATS]) {
+        let (mine, theirs) = self.data.boards(self.data.next_color());
+        (mine.combos.counts, theirs.combos.counts)
+    }
+    /// The moves played so far.
+    pub fn moves(&self) -> &[(usize, usize)] {
+        &self.data.white_board.moves
+    }
 
-        ai_data.white_board.put(p.0, p.1);
-        ai_data.black_board.put(p.0, p.1);
-    });
+    /// Plays `pos` for the player to move.
+    pub fn apply(&mut self, pos: (usize, usize)) -> Result<(), String> {
+        if pos.0 >= MAP_SIZE || pos.1 >= MAP_SIZE {
+            return Err(format!("({}, {}) is off the board", pos.0, pos.1));
+        }
+        if self.data.white_board.cells[pos.0][pos.1] != Cell::Empty {
+            return Err(format!("({}, {}) is taken", pos.0, pos.1));
+        }
+        self.data.put(pos.0, pos.1);
+        Ok(())
+    }
+    /// Takes back the last move, if there is one.
+    pub fn undo(&mut self) -> Option<(usize, usize)> {
+        let last = self.moves().last().cloned();
+        if last.is_some() {
+            self.data.undo();
+        }
+        last
+    }
+    /// Catches up with `board`, taking back the moves it does not share and playing the rest.
+    pub fn sync(&mut self, board: &Board) {
+        let common = self
+            .moves()
+            .iter()
+            .zip(board.moves())
+            .take_while(|(a, b)| a == b)
+            .count();
+        while self.moves().len() > common {
+            self.data.undo();
+        }
+        for &(x, y) in &board.moves()[common..] {
+            self.data.put(x, y);
+        }
+    }
+
+    /// The move the engine would play and how it was found, it is not played.
+    pub fn best_move(&mut self) -> Choice {
+        if let Some(pos) = self.book().and_then(|book| book.best_move(self.moves())) {
+            return Choice {
+                pos,
+                reason: Reason::Book,
+                value: 0.0,
+                searched: 0,
+            };
+        }
+
+        let (m, reason) = match self.method {
+            Method::AlphaBeta => threat_move(&mut self.data),
+            Method::Mcts(options) => (mcts_move(&mut self.data, &options), Reason::Playouts),
+        };
+        Choice {
+            pos: m.pos,
+            reason,
+            value: m.value,
+            searched: m.searched_count,
+        }
+    }
+    /// The value of every move worth considering for the player to move, each searched like
+    /// `evaluate` with `depth` counting the move itself. The threat search and the book are not used.
+    pub fn move_scores(&mut self, depth: u32) -> Vec<((usize, usize), f32)> {
+        candidate_moves(&self.data)
+            .into_iter()
+            .map(|pos| (pos, self.move_score(pos, depth)))
+            .collect()
+    }
+    /// The value of playing `pos`, which has to be empty, like one of `move_scores`.
+    pub fn move_score(&mut self, (x, y): (usize, usize), depth: u32) -> f32 {
+        let aggression = self.data.profile.aggression;
+        self.data.put(x, y);
+        let value = alphabeta(
+            &mut self.data,
+            &mut Budget::unlimited(),
+            false,
+            aggression,
+            depth.max(1) - 1,
+            std::f32::MIN,
+            std::f32::MAX,
+        )
+        .value;
+        self.data.undo();
+        value
+    }
+    /// The value of the position for the player to move, searched `depth` moves deep without
+    /// other limits. An open three is worth about 1, a won game 10000.
+    pub fn evaluate(&mut self, depth: u32) -> f32 {
+        let aggression = self.data.profile.aggression;
+        alphabeta(
+            &mut self.data,
+            &mut Budget::unlimited(),
+            true,
+            aggression,
+            depth,
+            std::f32::MIN,
+            std::f32::MAX,
+        )
+        .value
+    }
 }
 
-pub fn ai_undo() {
-    AIDATA_INSTANCE.with(|ai_data_pers| {
-        let mut ai_data = ai_data_pers.borrow_mut();
+/// How far below the best move a move still gets some heat in `heat_map`.
+const HEAT_RANGE: f32 = 3.0;
+/// The heat of the best move, the board stays visible under it.
+const MAX_HEAT: f32 = 0.6;
+
+/// Heat for `Board::heat` from the scores of `Engine::move_scores`, the best move is the hottest
+/// and moves an open three or more worse are left cold.
+pub fn heat_map(scores: &[((usize, usize), f32)]) -> [[f32; MAP_SIZE]; MAP_SIZE] {
+    let mut heat = [[0.0; MAP_SIZE]; MAP_SIZE];
+    let best = scores
+        .iter()
+        .map(|&(_, value)| value)
+        .fold(std::f32::MIN, f32::max);
+    for &((x, y), value) in scores {
+        heat[x][y] = MAX_HEAT * (1.0 - (best - value) / HEAT_RANGE).max(0.0);
+    }
+    heat
+}
 
-        ai_data.white_board.undo();
-        ai_data.black_board.undo();
-    });
+/// Searches forced wins for the player to move first, then keeps away from the forced wins
+/// of the opponent, choosing among the remaining moves with `alphabeta`.
+/// How far it looks and how many mistakes it makes depends on the level.
+fn threat_move(ai_data: &mut AiData) -> (Move, Reason) {
+    let level = ai_data.level;
+    let start = Instant::now();
+    let me = ai_data.next_color();
+    ai_data.reseed();
+    let mut moves = candidate_moves(ai_data);
+    let mut searched = 0;
+
+    if level.threat_search {
+        let mut tss = ThreatSearch::new(&ai_data.black_board, &ai_data.white_board, VCF_NODES);
+        tss.deadline = level.limits.time.map(|t| start + t);
+        if let Some(win) = forced_win(&mut tss, me) {
+            return win;
+        }
+        moves = defenses(&mut tss, me, moves);
+        searched = tss.nodes();
+    }
+
+    if !moves.is_empty() && ai_data.rng.unit() < level.blunder {
+        let random = Move {
+            value: 0.0,
+            pos: moves[ai_data.rng.below(moves.len())],
+            searched_count: searched,
+        };
+        return (random, Reason::Random);
+    }
+
+    // The threat search counts against the limits of the level.
+    let limits = SearchLimits {
+        time: level.limits.time.map(|t| t.saturating_sub(start.elapsed())),
+        nodes: level.limits.nodes.map(|n| n.saturating_sub(searched)),
+        ..level.limits
+    };
+    let aggression = ai_data.profile.aggression;
+    let mut best = deepening(ai_data, moves, &limits, aggression);
+    best.searched_count += searched;
+    (best, Reason::Search)
 }
 
-pub fn ai_move(board: &mut Board) -> GameResult {
-    let pos = AIDATA_INSTANCE.with(|ai_data_pers| {
-        let mut ai_data = ai_data_pers.borrow_mut();
+fn forced_win(tss: &mut ThreatSearch, me: Cell) -> Option<(Move, Reason)> {
+    if let Some(line) = tss.vcf(me, VCF_DEPTH) {
+        let win = Move {
+            value: 10000.0,
+            pos: line[0],
+            searched_count: tss.nodes(),
+        };
+        return Some((win, Reason::WinByFours(line)));
+    }
+    tss.node_limit = tss.nodes() + VCT_NODES;
+    if let Some(pos) = tss.vct(me, VCT_DEPTH) {
+        let win = Move {
+            value: 10000.0,
+            pos,
+            searched_count: tss.nodes(),
+        };
+        return Some((win, Reason::WinByThreats));
+    }
+    None
+}
 
-        if let Some(m) = board.moves().last() {
-            ai_data.white_board.put(m.0, m.1);
-            ai_data.black_board.put(m.0, m.1);
+/// The `moves` after which the opponent has no forced win, or all of them if there are none.
+/// Once the time of the search has passed the moves left are kept without looking.
+fn defenses(tss: &mut ThreatSearch, me: Cell, moves: Vec<(usize, usize)>) -> Vec<(usize, usize)> {
+    let opponent = me.opponent();
+    let wins = |tss: &mut ThreatSearch, vcf: bool, nodes: usize| {
+        tss.node_limit = tss.nodes() + nodes;
+        if vcf {
+            tss.vcf(opponent, VCF_DEPTH).is_some()
+        } else {
+            tss.vct(opponent, VCT_DEPTH).is_some()
+        }
+    };
+    for &vcf in &[true, false] {
+        if !wins(tss, vcf, VCF_NODES) {
+            continue;
+        }
+        let safe: Vec<(usize, usize)> = moves
+            .iter()
+            .cloned()
+            .filter(|&m| {
+                if tss.out_of_time() {
+                    return true;
+                }
+                tss.put(m, me);
+                let safe = !wins(tss, vcf, DEFENSE_NODES);
+                tss.put(m, Cell::Empty);
+                safe
+            })
+            .collect();
+        if !safe.is_empty() {
+            return safe;
+        }
+    }
+    moves
+}
+
+/// Searches one move deeper at a time until the limits run out,
+/// the result of the deepest finished search is returned.
+fn deepening(
+    ai_data: &mut AiData,
+    mut moves: Vec<(usize, usize)>,
+    limits: &SearchLimits,
+    aggression: f32,
+) -> Move {
+    let threads = ai_data.threads.min(moves.len()).max(1);
+    let mut budgets: Vec<Budget> = (0..threads)
+        .map(|_| {
+            let mut budget = Budget::new(limits);
+            budget.nodes = limits.nodes.map(|n| n / threads);
+            budget
+        })
+        .collect();
+    for killers in ai_data.killers.iter_mut() {
+        *killers = [None; 2];
+    }
+    ai_data.history = [[0; MAP_SIZE]; MAP_SIZE];
+    order_moves(ai_data, &mut moves, None, 2);
+    let mut helpers: Vec<AiData> = (1..threads).map(|id| ai_data.helper(id)).collect();
+    let mut best = Move {
+        value: std::f32::MIN,
+        pos: moves
+            .first()
+            .cloned()
+            .unwrap_or((MAP_SIZE / 2, MAP_SIZE / 2)),
+        searched_count: 0,
+    };
+
+    for depth in 1..=limits.depth.max(1) {
+        let found = if helpers.is_empty() {
+            search_root(ai_data, &mut budgets[0], &moves, aggression, depth)
+        } else {
+            search_split(
+                ai_data,
+                &mut helpers,
+                &mut budgets,
+                &moves,
+                aggression,
+                depth,
+            )
+        };
+        match found {
+            Some(m) => best = m,
+            None => break,
+        }
+        for budget in budgets.iter_mut() {
+            budget.armed = true;
+        }
+
+        if let Some(id) = moves.iter().position(|p| *p == best.pos) {
+            let pos = moves.remove(id);
+            moves.insert(0, pos);
+        }
+        if best.value.abs() >= 10000.0 {
+            break;
         }
+    }
+
+    best.searched_count = budgets.iter().map(|b| b.searched).sum();
+    best
+}
+
+/// `search_root` with the moves dealt out among `ai_data` and the `helpers`,
+/// each searching its share on its own thread.
+fn search_split(
+    ai_data: &mut AiData,
+    helpers: &mut [AiData],
+    budgets: &mut [Budget],
+    moves: &[(usize, usize)],
+    aggression: f32,
+    depth: u32,
+) -> Option<Move> {
+    let threads = helpers.len() + 1;
+    let shares: Vec<Vec<(usize, usize)>> = (0..threads)
+        .map(|id| moves.iter().cloned().skip(id).step_by(threads).collect())
+        .collect();
+
+    let found: Vec<Option<Move>> = thread::scope(|scope| {
+        let workers = std::iter::once(&mut *ai_data).chain(helpers.iter_mut());
+        let handles: Vec<_> = workers
+            .zip(budgets.iter_mut())
+            .zip(&shares)
+            .map(|((data, budget), share)| {
+                scope.spawn(move || search_root(data, budget, share, aggression, depth))
+            })
+            .collect();
+        handles
+            .into_iter()
+            .map(|h| h.join().expect("search thread panicked"))
+            .collect()
+    });
+
+    let mut best: Option<Move> = None;
+    for m in found {
+        let m = m?;
+        let better = |b: &Move, rng: &mut Random| {
+            m.value > b.value + TIE || (m.value > b.value - TIE && rng.below(2) == 0)
+        };
+        best = match best {
+            Some(b) if !better(&b, &mut ai_data.rng) => Some(Move {
+                searched_count: b.searched_count + m.searched_count,
+                ..b
+            }),
+            Some(b) => Some(Move {
+                searched_count: b.searched_count + m.searched_count,
+                ..m
+            }),
+            None => Some(m),
+        };
+    }
+    best
+}
+
+/// How many moves a win by fours may take, and how many positions are looked at for one.
+const VCF_DEPTH: u32 = 12;
+const VCF_NODES: usize = 5000;
+/// The same for wins by fours and threes, these branch a lot more.
+const VCT_DEPTH: u32 = 4;
+const VCT_NODES: usize = 2000;
+/// Positions looked at per candidate move when checking that it stops a forced win.
+const DEFENSE_NODES: usize = 500;
+
+/// Moves with values closer than this are equally good, one of them is chosen at random.
+const TIE: f32 = 1e-3;
+
+/// `alphabeta` for the player to move, looking only at `moves`.
+///
+/// Returns `None` if the budget ran out before every move was searched.
+fn search_root(
+    ai_data: &mut AiData,
+    budget: &mut Budget,
+    moves: &[(usize, usize)],
+    aggression: f32,
+    depth: u32,
+) -> Option<Move> {
+    let mut best = Move {
+        value: std::f32::MIN,
+        pos: moves
+            .first()
+            .cloned()
+            .unwrap_or((MAP_SIZE / 2, MAP_SIZE / 2)),
+        searched_count: 0,
+    };
+    let mut alpha = std::f32::MIN;
+    let mut ties = 0;
+
+    for &(n, k) in moves {
+        ai_data.put(n, k);
 
         let Move {
             value,
-            pos,
             searched_count,
-        } = alphabeta(&mut ai_data, true, 0.51, 3, std::f32::MIN, std::f32::MAX);
-
-        println!(
-            "AI searched through {} operations, value is {}",
-            searched_count, value
+            ..
+        } = alphabeta(
+            ai_data,
+            budget,
+            false,
+            aggression,
+            depth - 1,
+            alpha,
+            std::f32::MAX,
         );
+        best.searched_count += searched_count;
 
-        // println!("black has combos: {:?}", ai_data.black_board.combos);
+        ai_data.undo();
 
-        ai_data.white_board.put(pos.0, pos.1);
-        ai_data.black_board.put(pos.0, pos.1);
+        if budget.stopped {
+            return None;
+        }
 
-        pos
-    });
+        // A value at or below alpha is only an upper bound, the move can't catch up
+        // with the best one even with noise, so only exact values get noise.
+        let noise = ai_data.level.noise;
+        if value > alpha {
+            let noisy = value + (ai_data.rng.unit() * 2.0 - 1.0) * noise;
+            if noisy > best.value + TIE {
+                best.value = noisy;
+                best.pos = (n, k);
+                ties = 1;
+            } else if noisy > best.value - TIE {
+                // Each of the equal moves is kept with the same chance.
+                ties += 1;
+                if ai_data.rng.below(ties) == 0 {
+                    best.pos = (n, k);
+                }
+            }
+        }
+        // Moves that could tie with the best one after their noise have to get their exact value.
+        alpha = alpha.max(best.value - noise - TIE);
+    }
 
-    board.put(pos.0, pos.1)
+    Some(best)
 }
 
 #[derive(Copy, Clone)]
@@ -129,6 +979,7 @@ struct Move {
 
 fn alphabeta(
     ai_data: &mut AiData,
+    budget: &mut Budget,
     my_turn: bool,
     mut aggression: f32,
     depth: u32,
@@ -137,9 +988,18 @@ fn alphabeta(
 ) -> Move {
     let black_turn = ai_data.white_board.moves.len() % 2 == 0;
 
+    if budget.exhausted() {
+        return Move {
+            value: 0.0,
+            pos: (0, 0),
+            searched_count: 0,
+        };
+    }
+
     if depth == 0 {
-        let white_val = ai_data.white_board.combos.eval() as f32;
-        let black_val = ai_data.black_board.combos.eval() as f32;
+        budget.searched += 1;
+        let white_val = ai_data.white_board.eval() as f32;
+        let black_val = ai_data.black_board.eval() as f32;
 
         let (my_val, enemy_val) = if black_turn && my_turn || !black_turn && !my_turn {
             (black_val, white_val)
@@ -158,7 +1018,31 @@ fn alphabeta(
         };
     }
 
-    if ai_data.black_board.combos.fives > 0 || ai_data.white_board.combos.fives > 0 {
+    let key = ai_data.key(my_turn);
+    let (alpha_start, beta_start) = (alpha, beta);
+    let mut table_move = None;
+    if let Some(entry) = ai_data.table.probe(key) {
+        let best = (entry.best.0 as usize, entry.best.1 as usize);
+        if entry.depth as u32 >= depth {
+            let cut = match entry.bound {
+                Bound::Exact => true,
+                Bound::Lower => entry.value >= beta,
+                Bound::Upper => entry.value <= alpha,
+            };
+            if cut {
+                return Move {
+                    value: entry.value,
+                    pos: best,
+                    searched_count: 0,
+                };
+            }
+        }
+        table_move = Some(best);
+    }
+
+    if ai_data.black_board.combos.get(Threat::Five) > 0
+        || ai_data.white_board.combos.get(Threat::Five) > 0
+    {
         // println!("I run on an over");
         return Move {
             value: if my_turn { -10000.0 } else { 10000.0 },
@@ -183,6 +1067,149 @@ fn alphabeta(
     //     };
     // }
 
+    let mut mx_value = std::f32::MIN;
+    let mut mx_pos = (0, 0);
+    let mut mn_value = std::f32::MAX;
+    let mut mn_pos = (0, 0);
+    let mut searched = 0;
+
+    let mut possible_moves = candidate_moves(ai_data);
+    ai_data.rng.shuffle(&mut possible_moves);
+    order_moves(ai_data, &mut possible_moves, table_move, depth);
+
+    for (n, k) in possible_moves {
+        ai_data.put(n, k);
+
+        let Move {
+            value,
+            pos: _,
+            searched_count,
+        } = alphabeta(
+            ai_data,
+            budget,
+            !my_turn,
+            aggression,
+            depth - 1,
+            alpha,
+            beta,
+        );
+        searched += searched_count;
+
+        if my_turn && alpha < value {
+            alpha = value;
+        }
+        if !my_turn && beta > value {
+            beta = value;
+        }
+
+        if mx_value < value {
+            mx_value = value;
+            mx_pos = (n, k);
+        }
+        if mn_value > value {
+            mn_value = value;
+            mn_pos = (n, k);
+        }
+
+        ai_data.undo();
+
+        if alpha >= beta {
+            ai_data.cutoff((n, k), depth);
+            break;
+        }
+        if budget.stopped {
+            break;
+        }
+    }
+
+    let best = if my_turn {
+        Move {
+            value: mx_value,
+            pos: mx_pos,
+            searched_count: searched,
+        }
+    } else {
+        Move {
+            value: mn_value,
+            pos: mn_pos,
+            searched_count: searched,
+        }
+    };
+
+    if !budget.stopped {
+        let bound = if best.value <= alpha_start {
+            Bound::Upper
+        } else if best.value >= beta_start {
+            Bound::Lower
+        } else {
+            Bound::Exact
+        };
+        ai_data.table.store(TableEntry {
+            key,
+            value: best.value,
+            depth: depth.min(255) as u8,
+            bound,
+            best: (best.pos.0 as u8, best.pos.1 as u8),
+        });
+    }
+
+    best
+}
+
+/// Killer moves are tried after moves making or blocking an open three.
+const KILLER_BONUS: f64 = 0.5;
+/// Cutoffs of the history table break ties between moves of equal threats.
+const HISTORY_WEIGHT: f64 = 1e-6;
+
+/// Sorts `moves` so the likely best ones come first: `first`, then the ones making and blocking
+/// the most threats, killer moves and moves with many cutoffs before.
+/// Moves scoring the same keep their order, so shuffling first picks randomly among them.
+fn order_moves(
+    ai_data: &mut AiData,
+    moves: &mut Vec<(usize, usize)>,
+    first: Option<(usize, usize)>,
+    depth: u32,
+) {
+    let killers = ai_data.killers[ai_data.white_board.moves.len()];
+    let mut scored: Vec<(f64, (usize, usize))> = moves
+        .iter()
+        .map(|&m| {
+            let mut score = ai_data.history[m.0][m.1] as f64 * HISTORY_WEIGHT;
+            if killers.contains(&Some(m)) {
+                score += KILLER_BONUS;
+            }
+            if depth >= 2 {
+                score += threat_delta(ai_data, m);
+            }
+            if Some(m) == first {
+                score = std::f64::MAX;
+            }
+            (score, m)
+        })
+        .collect();
+
+    scored.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap_or(Ordering::Equal));
+    *moves = scored.into_iter().map(|(_, m)| m).collect();
+}
+
+/// How much the threats of the player to move grow and the ones of the opponent shrink by playing `m`.
+fn threat_delta(ai_data: &mut AiData, m: (usize, usize)) -> f64 {
+    let player = ai_data.next_color();
+    let evals = |ai_data: &AiData| {
+        let (mine, theirs) = ai_data.boards(player);
+        (mine.eval(), theirs.eval())
+    };
+
+    let before = evals(ai_data);
+    ai_data.put(m.0, m.1);
+    let after = evals(ai_data);
+    ai_data.undo();
+
+    (after.0 - before.0) + (before.1 - after.1)
+}
+
+/// The empty intersections near stones, and the center of the board.
+fn candidate_moves(ai_data: &AiData) -> Vec<(usize, usize)> {
     let mut close = [[false; MAP_SIZE]; MAP_SIZE];
     {
         let mut close1 = [[false; MAP_SIZE]; MAP_SIZE];
@@ -230,12 +1257,6 @@ fn alphabeta(
 
     close[MAP_SIZE / 2][MAP_SIZE / 2] = true;
 
-    let mut mx_value = std::f32::MIN;
-    let mut mx_pos = (0, 0);
-    let mut mn_value = std::f32::MAX;
-    let mut mn_pos = (0, 0);
-    let mut searched = 0;
-
     let mut possible_moves = vec![];
 
     for n in 0..MAP_SIZE {
@@ -246,144 +1267,118 @@ fn alphabeta(
         }
     }
 
-    possible_moves.shuffle(&mut thread_rng());
-
-    for (n, k) in possible_moves {
-        ai_data.white_board.put(n, k);
-        ai_data.black_board.put(n, k);
-
-        let Move {
-            value,
-            pos: _,
-            searched_count,
-        } = alphabeta(ai_data, !my_turn, aggression, depth - 1, alpha, beta);
-        searched += searched_count;
-
-        if my_turn && alpha < value {
-            alpha = value;
-        }
-        if !my_turn && beta > value {
-            beta = value;
-        }
-
-        if mx_value < value {
-            mx_value = value;
-            mx_pos = (n, k);
-        }
-        if mn_value > value {
-            mn_value = value;
-            mn_pos = (n, k);
-        }
+    possible_moves
+}
 
-        ai_data.black_board.undo();
-        ai_data.white_board.undo();
+/// How many of each threat a player has on the board.
+#[derive(Debug, Default, Copy, Clone, PartialEq, Eq)]
+struct Combinations {
+    counts: [u8; THREATS],
+}
 
-        if alpha >= beta {
-            break;
+impl std::ops::AddAssign for Combinations {
+    fn add_assign(&mut self, rhs: Combinations) {
+        for (c, r) in self.counts.iter_mut().zip(&rhs.counts) {
+            *c += r;
         }
     }
+}
 
-    if my_turn {
-        Move {
-            value: mx_value,
-            pos: mx_pos,
-            searched_count: searched,
-        }
-    } else {
-        Move {
-            value: mn_value,
-            pos: mn_pos,
-            searched_count: searched,
+impl std::ops::SubAssign for Combinations {
+    fn sub_assign(&mut self, rhs: Combinations) {
+        for (c, r) in self.counts.iter_mut().zip(&rhs.counts) {
+            *c -= r;
         }
     }
 }
 
-#[derive(Debug, Default, Copy, Clone, Eq)]
-struct Combinations {
-    fives: u8,
-    open_fours: u8,
-    half_open_fours: u8,
-    open_threes: u8,
-    half_open_threes: u8,
-    open_twos: u8,
+impl Combinations {
+    fn count(&mut self, threat: Threat) {
+        self.counts[threat as usize] += 1;
+    }
+    fn get(&self, threat: Threat) -> u8 {
+        self.counts[threat as usize]
+    }
+    fn eval(&self, weights: &[f64; THREATS]) -> f64 {
+        self.counts
+            .iter()
+            .zip(weights)
+            .map(|(&c, w)| c as f64 * w)
+            .sum()
+    }
 }
 
-impl PartialEq for Combinations {
-    fn eq(&self, other: &Self) -> bool {
-        if min(self.fives, other.fives) > 0 {
-            return self.fives == other.fives;
-        }
-        if min(self.open_fours, other.open_fours) > 0 {
-            return self.open_fours == other.open_fours;
-        }
-        self.eval() == other.eval()
+/// Lines are stored with two bits per intersection, starting with the edge before
+/// the first one, and every intersection past the end of the line is an edge.
+const EDGE: u64 = 3;
+
+fn cell_code(cell: Cell) -> u64 {
+    match cell {
+        Cell::Empty => 0,
+        Cell::Black => 1,
+        Cell::White => 2,
     }
 }
-impl PartialOrd for Combinations {
-    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
-        if min(self.fives, other.fives) > 0 {
-            return self.fives.partial_cmp(&other.fives);
-        }
-        if min(self.open_fours, other.open_fours) > 0 {
-            return self.open_fours.partial_cmp(&other.open_fours);
-        }
-        self.eval().partial_cmp(&other.eval())
-    }
+
+/// The intersections of a line of `len` starting with the edge before it, as stored.
+fn empty_line(len: usize) -> u64 {
+    !0u64 << (2 * (len + 1)) | EDGE
 }
 
-impl std::ops::AddAssign for Combinations {
-    fn add_assign(&mut self, rhs: Combinations) {
-        self.fives += rhs.fives;
-        self.open_fours += rhs.open_fours;
-        self.half_open_fours += rhs.half_open_fours;
-        self.open_threes += rhs.open_threes;
-        self.half_open_threes += rhs.half_open_threes;
-        self.open_twos += rhs.open_twos;
-    }
+/// The intersections of `line` from the `start`-th on, as an index into the pattern table.
+fn window(line: u64, start: usize) -> usize {
+    ((line >> (2 * start)) & ((1 << (2 * MAX_LEN)) - 1)) as usize
 }
 
-impl std::ops::SubAssign for Combinations {
-    fn sub_assign(&mut self, rhs: Combinations) {
-        self.fives -= rhs.fives;
-        self.open_fours -= rhs.open_fours;
-        self.half_open_fours -= rhs.half_open_fours;
-        self.open_threes -= rhs.open_threes;
-        self.half_open_threes -= rhs.half_open_threes;
-        self.open_twos -= rhs.open_twos;
-    }
+/// The threats of `player` starting at the first intersection of every possible window.
+fn pattern_table(player: Cell, shapes: &Shapes) -> Vec<Combinations> {
+    let square = |code: usize| match code as u64 {
+        0 => Square::Empty,
+        EDGE => Square::Edge,
+        c if c == cell_code(player) => Square::Mine,
+        _ => Square::Theirs,
+    };
+    (0..1usize << (2 * MAX_LEN))
+        .map(|w| {
+            let line: Vec<Square> = (0..MAX_LEN).map(|i| square((w >> (2 * i)) & 3)).collect();
+            let mut combos = Combinations::default();
+            for shape in &shapes.shapes {
+                if shape.matches(&line) {
+                    combos.count(shape.threat);
+                }
+            }
+            combos
+        })
+        .collect()
 }
 
-impl Combinations {
-    fn eval(&self) -> f64 {
-        self.fives as f64 * 100.0
-            + self.open_fours as f64 * 10.0
-            + self.half_open_fours as f64 * 5.0
-            + self.open_threes as f64 * 1.0
-            + self.half_open_threes as f64 * 0.1
-            + self.open_twos as f64 * 0.01
+fn threat_spans(shapes: &Shapes) -> [(usize, usize); THREATS] {
+    let mut spans = [(MAX_LEN, 0); THREATS];
+    for shape in &shapes.shapes {
+        let stones = shape.squares.iter().filter(|&&s| s == Square::Mine).count();
+        let span = &mut spans[shape.threat as usize];
+        *span = (span.0.min(stones), span.1.max(shape.squares.len()));
     }
+    spans
 }
 
-#[derive(Debug, Default)]
+#[derive(Debug, Default, Clone)]
 struct BoardAsNums {
-    rows: [u32; MAP_SIZE],
-    cols: [u32; MAP_SIZE],
-    rds: [u32; MAP_SIZE * 2 - 1],
-    rus: [u32; MAP_SIZE * 2 - 1],
+    rows: [u64; MAP_SIZE],
+    cols: [u64; MAP_SIZE],
+    rds: [u64; MAP_SIZE * 2 - 1],
+    rus: [u64; MAP_SIZE * 2 - 1],
     cells: [[Cell; MAP_SIZE]; MAP_SIZE],
     moves: Vec<(usize, usize)>,
     combos: Combinations,
-    player: Cell,
-    num_to_combos: [HashMap<u32, Combinations>; MAP_SIZE + 1],
+    /// The threats of the player for every window, see `pattern_table`, shared by the copies of the board.
+    patterns: Arc<Vec<Combinations>>,
+    /// The fewest stones of the player and the most intersections in a shape of each threat.
+    spans: [(usize, usize); THREATS],
+    weights: [f64; THREATS],
+    forks: Forks,
 }
 
-fn proj_rds(x: usize, y: usize) -> (i32, i32) {
-    if x < y {
-        (0, (y - x) as i32)
-    } else {
-        ((x - y) as i32, 0)
-    }
-}
 fn len_rds(x: usize, y: usize) -> usize {
     MAP_SIZE - (x as i32 - y as i32).abs() as usize
 }
@@ -394,13 +1389,6 @@ fn adv_rds(x: usize, y: usize) -> usize {
     min(x, y)
 }
 
-fn proj_rus(x: usize, y: usize) -> (i32, i32) {
-    if x + y < MAP_SIZE - 1 {
-        (0, (y + x) as i32)
-    } else {
-        ((x + y - (MAP_SIZE - 1)) as i32, (MAP_SIZE - 1) as i32)
-    }
-}
 fn len_rus(x: usize, y: usize) -> usize {
     MAP_SIZE - ((x + y) as i32 - (MAP_SIZE - 1) as i32).abs() as usize
 }
@@ -411,367 +1399,246 @@ fn adv_rus(x: usize, y: usize) -> usize {
     min(x, MAP_SIZE - 1 - y)
 }
 
+/// Sets the `id`-th intersection of `line` to `cell`, taking the shapes starting
+/// in the windows that cover it out of `combos` and the new ones in.
+fn set_cell(
+    patterns: &[Combinations],
+    combos: &mut Combinations,
+    line: &mut u64,
+    id: usize,
+    cell: Cell,
+) {
+    let pos = id + 1;
+    let starts = pos.saturating_sub(MAX_LEN - 1)..=pos;
+    for start in starts.clone() {
+        *combos -= patterns[window(*line, start)];
+    }
+    *line = *line & !(3 << (2 * pos)) | cell_code(cell) << (2 * pos);
+    for start in starts {
+        *combos += patterns[window(*line, start)];
+    }
+}
+
 impl BoardAsNums {
-    pub fn new(player: Cell) -> BoardAsNums {
-        BoardAsNums {
-            player,
+    pub fn new(player: Cell, shapes: &Shapes) -> BoardAsNums {
+        let mut board = BoardAsNums {
+            rows: [empty_line(MAP_SIZE); MAP_SIZE],
+            cols: [empty_line(MAP_SIZE); MAP_SIZE],
+            patterns: Arc::new(pattern_table(player, shapes)),
+            spans: threat_spans(shapes),
+            weights: shapes.weights,
             ..Default::default()
+        };
+        for x in 0..MAP_SIZE {
+            for y in 0..MAP_SIZE {
+                board.rds[index_rds(x, y)] = empty_line(len_rds(x, y));
+                board.rus[index_rus(x, y)] = empty_line(len_rus(x, y));
+            }
         }
+        board
+    }
+    pub fn eval(&self) -> f64 {
+        self.combos.eval(&self.weights) + self.forks.bonus(&self.combos.counts)
     }
     pub fn put(&mut self, x: usize, y: usize) {
-        let colmod = (self.moves.len() % 2) as u32 + 1;
-        self.cells[x][y] = if self.moves.len() % 2 == 0 {
+        let cell = if self.moves.len() % 2 == 0 {
             Cell::Black
         } else {
             Cell::White
         };
         self.moves.push((x, y));
-
-        let sub = self.evaluate((0, y as i32), (1, 0), self.cols[y], MAP_SIZE);
-        self.combos -= sub;
-        self.cols[y] += 3u32.pow(x as u32) * colmod;
-        let add = self.evaluate((0, y as i32), (1, 0), self.cols[y], MAP_SIZE);
-        self.combos += add;
-
-        let sub = self.evaluate((x as i32, 0), (0, 1), self.rows[x], MAP_SIZE);
-        self.combos -= sub;
-        self.rows[x] += 3u32.pow(y as u32) * colmod;
-        let add = self.evaluate((x as i32, 0), (0, 1), self.rows[x], MAP_SIZE);
-        self.combos += add;
-
-        let sub = self.evaluate(
-            proj_rds(x, y),
-            (1, 1),
-            self.rds[index_rds(x, y)],
-            len_rds(x, y),
+        self.set(x, y, cell);
+    }
+    pub fn undo(&mut self) {
+        if let Some((x, y)) = self.moves.pop() {
+            self.set(x, y, Cell::Empty);
+        }
+    }
+    fn set(&mut self, x: usize, y: usize, cell: Cell) {
+        self.cells[x][y] = cell;
+        let (patterns, combos) = (&self.patterns, &mut self.combos);
+        set_cell(patterns, combos, &mut self.cols[y], x, cell);
+        set_cell(patterns, combos, &mut self.rows[x], y, cell);
+        set_cell(
+            patterns,
+            combos,
+            &mut self.rds[index_rds(x, y)],
+            adv_rds(x, y),
+            cell,
         );
-        self.combos -= sub;
-        self.rds[index_rds(x, y)] += 3u32.pow(adv_rds(x, y) as u32) * colmod;
-        let add = self.evaluate(
-            proj_rds(x, y),
-            (1, 1),
-            self.rds[index_rds(x, y)],
-            len_rds(x, y),
+        set_cell(
+            patterns,
+            combos,
+            &mut self.rus[index_rus(x, y)],
+            adv_rus(x, y),
+            cell,
         );
-        self.combos += add;
+    }
+}
 
-        let sub = self.evaluate(
-            proj_rus(x, y),
-            (1, -1),
-            self.rus[index_rus(x, y)],
-            len_rus(x, y),
-        );
-        self.combos -= sub;
-        self.rus[index_rus(x, y)] += 3u32.pow(adv_rus(x, y) as u32) * colmod;
-        let add = self.evaluate(
-            proj_rus(x, y),
-            (1, -1),
-            self.rus[index_rus(x, y)],
-            len_rus(x, y),
-        );
-        self.combos += add;
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The pattern tables count the same threats as `Shapes::threats` in every direction.
+    #[test]
+    fn tables_count_like_the_shapes() {
+        let shapes = Shapes::default();
+        let lines = [
+            "_XX_XX_", "OXXX_XO", "XX_X_", "__XXX__", "XXXX_", "_X_XXO", "O_XX_X_",
+        ];
+        let directions: [fn(usize) -> (usize, usize); 4] = [
+            |i| (0, i),
+            |i| (i, 0),
+            |i| (i, i),
+            |i| (i, MAP_SIZE - 1 - i),
+        ];
+        for line in &lines {
+            let line = format!("{:_<width$}", line, width = MAP_SIZE);
+            let mut expected = Combinations::default();
+            for threat in shapes.threats(&line).unwrap() {
+                expected.count(threat);
+            }
+            for pos in &directions {
+                let mut board = BoardAsNums::new(Cell::Black, &shapes);
+                for (i, c) in line.chars().enumerate() {
+                    let (x, y) = pos(i);
+                    match c {
+                        'X' => board.set(x, y, Cell::Black),
+                        'O' => board.set(x, y, Cell::White),
+                        _ => {}
+                    }
+                }
+                assert_eq!(board.combos, expected, "{}", line);
+            }
+        }
     }
-    pub fn undo(&mut self) {
-        if let Some((x, y)) = self.moves.pop() {
-            let colmod = (self.moves.len() % 2) as u32 + 1;
-            self.cells[x][y] = Cell::Empty;
-
-            let sub = self.evaluate((0, y as i32), (1, 0), self.cols[y], MAP_SIZE);
-            self.combos -= sub;
-            self.cols[y] -= 3u32.pow(x as u32) * colmod;
-            let add = self.evaluate((0, y as i32), (1, 0), self.cols[y], MAP_SIZE);
-            self.combos += add;
-
-            let sub = self.evaluate((x as i32, 0), (0, 1), self.rows[x], MAP_SIZE);
-            self.combos -= sub;
-            self.rows[x] -= 3u32.pow(y as u32) * colmod;
-            let add = self.evaluate((x as i32, 0), (0, 1), self.rows[x], MAP_SIZE);
-            self.combos += add;
-
-            let sub = self.evaluate(
-                proj_rds(x, y),
-                (1, 1),
-                self.rds[index_rds(x, y)],
-                len_rds(x, y),
-            );
-            self.combos -= sub;
-            self.rds[index_rds(x, y)] -= 3u32.pow(adv_rds(x, y) as u32) * colmod;
-            let add = self.evaluate(
-                proj_rds(x, y),
-                (1, 1),
-                self.rds[index_rds(x, y)],
-                len_rds(x, y),
-            );
-            self.combos += add;
 
-            let sub = self.evaluate(
-                proj_rus(x, y),
-                (1, -1),
-                self.rus[index_rus(x, y)],
-                len_rus(x, y),
-            );
-            self.combos -= sub;
-            self.rus[index_rus(x, y)] -= 3u32.pow(adv_rus(x, y) as u32) * colmod;
-            let add = self.evaluate(
-                proj_rus(x, y),
-                (1, -1),
-                self.rus[index_rus(x, y)],
-                len_rus(x, y),
+    #[test]
+    fn table_entries_survive_packing() {
+        let table = TransTable::new(16);
+        let entry = TableEntry {
+            key: 0xdead_beef_1234_5678,
+            value: -1.25,
+            depth: 7,
+            bound: Bound::Upper,
+            best: (14, 3),
+        };
+        assert_eq!(table.probe(entry.key), None);
+        table.store(entry);
+        assert_eq!(table.probe(entry.key), Some(entry));
+        assert_eq!(table.probe(entry.key + 16), None);
+        table.clear();
+        assert_eq!(table.probe(entry.key), None);
+    }
+
+    #[test]
+    fn equal_moves_depend_on_the_seed() {
+        let mut board = Board::default();
+        board.put(7, 7);
+        let answer = |seed: u64| {
+            let mut engine = Engine::new(&board);
+            engine.set_level(Level::by_name("Hard").unwrap());
+            engine.set_limits(SearchLimits {
+                depth: 2,
+                time: None,
+                nodes: None,
+            });
+            engine.set_threads(1);
+            engine.set_seed(seed);
+            engine.best_move().pos
+        };
+
+        assert_eq!(answer(1), answer(1));
+        let answers: Vec<_> = (0..8).map(answer).collect();
+        assert!(answers.iter().any(|&a| a != answers[0]), "{:?}", answers);
+    }
+
+    type Scores = Vec<((usize, usize), f32)>;
+
+    /// The moves chosen at `level` for seeds 0 to 7 after a few opening moves, along with
+    /// the exact value of each move.
+    fn answers_at(level: Level) -> (Vec<(usize, usize)>, Scores) {
+        let mut board = Board::default();
+        for &(x, y) in &[(7, 7), (6, 8), (8, 8), (8, 6)] {
+            board.put(x, y);
+        }
+        let mut engine = Engine::new(&board);
+        engine.set_level(level);
+        engine.set_threads(1);
+        let answers = (0..8)
+            .map(|seed| {
+                engine.set_seed(seed);
+                engine.best_move().pos
+            })
+            .collect();
+        (answers, engine.move_scores(level.limits.depth))
+    }
+
+    #[test]
+    fn noise_only_changes_noisy_levels() {
+        let limits = SearchLimits {
+            depth: 2,
+            time: None,
+            nodes: None,
+        };
+        let exact = Level {
+            limits,
+            noise: 0.0,
+            blunder: 0.0,
+            ..LEVELS[0]
+        };
+        let (answers, scores) = answers_at(exact);
+        let best = scores.iter().map(|s| s.1).fold(std::f32::MIN, f32::max);
+        for pos in &answers {
+            let value = scores.iter().find(|s| s.0 == *pos).unwrap().1;
+            assert!(value > best - TIE, "{:?} {} {}", pos, value, best);
+        }
+
+        let noisy = Level {
+            noise: LEVELS[0].noise,
+            ..exact
+        };
+        let (answers, scores) = answers_at(noisy);
+        assert!(answers.iter().any(|&a| a != answers[0]), "{:?}", answers);
+        for pos in &answers {
+            let value = scores.iter().find(|s| s.0 == *pos).unwrap().1;
+            assert!(
+                value >= best - 2.0 * noisy.noise - TIE,
+                "{:?} {} {}",
+                pos,
+                value,
+                best
             );
-            self.combos += add;
         }
     }
-    pub fn evaluate(&mut self, p: (i32, i32), v: (i32, i32), num: u32, len: usize) -> Combinations {
-        if let Some(v) = self.num_to_combos[len].get(&num) {
-            *v
-        } else {
-            let line = self.line(p, v).collect::<Vec<Cell>>();
-            let opponent = self.player.opponent();
-            let mut ret = Combinations::default();
-            let player = self.player;
-            for i in 0..len {
-                if i + 5 <= len
-                    && line[i] == player
-                    && line[i + 1] == player
-                    && line[i + 2] == player
-                    && line[i + 3] == player
-                    && line[i + 4] == player
-                {
-                    ret.fives += 1;
-                }
-                if i + 6 <= len
-                    && line[i] == Cell::Empty
-                    && line[i + 1] == player
-                    && line[i + 2] == player
-                    && line[i + 3] == player
-                    && line[i + 4] == player
-                    && line[i + 5] == Cell::Empty
-                {
-                    ret.open_fours += 1;
-                }
-                if i + 6 <= len
-                    && line[i] == opponent
-                    && line[i + 1] == player
-                    && line[i + 2] == player
-                    && line[i + 3] == player
-                    && line[i + 4] == player
-                    && line[i + 5] == Cell::Empty
-                {
-                    ret.half_open_fours += 1;
-                }
-                if i + 6 <= len
-                    && line[i] == opponent
-                    && line[i + 1] == player
-                    && line[i + 2] == player
-                    && line[i + 3] == player
-                    && line[i + 4] == Cell::Empty
-                    && line[i + 5] == player
-                {
-                    ret.half_open_fours += 1;
-                }
-                if i + 6 <= len
-                    && line[i] == opponent
-                    && line[i + 1] == player
-                    && line[i + 2] == player
-                    && line[i + 3] == Cell::Empty
-                    && line[i + 4] == player
-                    && line[i + 5] == player
-                {
-                    ret.half_open_fours += 1;
-                }
-                if i + 6 <= len
-                    && line[i] == opponent
-                    && line[i + 1] == player
-                    && line[i + 2] == Cell::Empty
-                    && line[i + 3] == player
-                    && line[i + 4] == player
-                    && line[i + 5] == player
-                {
-                    ret.half_open_fours += 1;
-                }
-                if i + 6 <= len
-                    && line[i] == Cell::Empty
-                    && line[i + 1] == player
-                    && line[i + 2] == player
-                    && line[i + 3] == player
-                    && line[i + 4] == player
-                    && line[i + 5] == opponent
-                {
-                    ret.half_open_fours += 1;
-                }
-                if i + 6 <= len
-                    && line[i] == player
-                    && line[i + 1] == Cell::Empty
-                    && line[i + 2] == player
-                    && line[i + 3] == player
-                    && line[i + 4] == player
-                    && line[i + 5] == opponent
-                {
-                    ret.half_open_fours += 1;
-                }
-                if i + 6 <= len
-                    && line[i] == player
-                    && line[i + 1] == player
-                    && line[i + 2] == Cell::Empty
-                    && line[i + 3] == player
-                    && line[i + 4] == player
-                    && line[i + 5] == opponent
-                {
-                    ret.half_open_fours += 1;
-                }
-                if i + 6 <= len
-                    && line[i] == player
-                    && line[i + 1] == player
-                    && line[i + 2] == player
-                    && line[i + 3] == Cell::Empty
-                    && line[i + 4] == player
-                    && line[i + 5] == opponent
-                {
-                    ret.half_open_fours += 1;
-                }
-                if len >= 5
-                    && i == 0
-                    && line[i] == player
-                    && line[i + 1] == player
-                    && line[i + 2] == player
-                    && line[i + 3] == player
-                    && line[i + 4] == Cell::Empty
-                {
-                    ret.half_open_fours += 1;
-                }
-                if len >= 5
-                    && i == len - 5
-                    && line[i] == Cell::Empty
-                    && line[i + 1] == player
-                    && line[i + 2] == player
-                    && line[i + 3] == player
-                    && line[i + 4] == player
-                {
-                    ret.half_open_fours += 1;
-                }
-                if i + 5 <= len
-                    && line[i] == Cell::Empty
-                    && line[i + 1] == player
-                    && line[i + 2] == player
-                    && line[i + 3] == player
-                    && line[i + 4] == Cell::Empty
-                {
-                    ret.open_threes += 1;
-                }
-                if i + 6 <= len
-                    && line[i] == Cell::Empty
-                    && line[i + 1] == player
-                    && line[i + 2] == player
-                    && line[i + 3] == Cell::Empty
-                    && line[i + 4] == player
-                    && line[i + 5] == Cell::Empty
-                {
-                    ret.open_threes += 1;
-                }
-                if i + 6 <= len
-                    && line[i] == Cell::Empty
-                    && line[i + 1] == player
-                    && line[i + 2] == Cell::Empty
-                    && line[i + 3] == player
-                    && line[i + 4] == player
-                    && line[i + 5] == Cell::Empty
-                {
-                    ret.open_threes += 1;
-                }
-                if i + 5 <= len
-                    && line[i] == Cell::Empty
-                    && line[i + 1] == player
-                    && line[i + 2] == player
-                    && line[i + 3] == player
-                    && line[i + 4] == opponent
-                {
-                    ret.half_open_threes += 1;
-                }
-                if i + 6 <= len
-                    && line[i] == Cell::Empty
-                    && line[i + 1] == player
-                    && line[i + 2] == Cell::Empty
-                    && line[i + 3] == player
-                    && line[i + 4] == player
-                    && line[i + 5] == opponent
-                {
-                    ret.half_open_threes += 1;
-                }
-                if i + 6 <= len
-                    && line[i] == Cell::Empty
-                    && line[i + 1] == player
-                    && line[i + 2] == player
-                    && line[i + 3] == Cell::Empty
-                    && line[i + 4] == player
-                    && line[i + 5] == opponent
-                {
-                    ret.half_open_threes += 1;
-                }
-                if i + 5 <= len
-                    && line[i] == opponent
-                    && line[i + 1] == player
-                    && line[i + 2] == player
-                    && line[i + 3] == player
-                    && line[i + 4] == Cell::Empty
-                {
-                    ret.half_open_threes += 1;
-                }
-                if i + 6 <= len
-                    && line[i] == opponent
-                    && line[i + 1] == player
-                    && line[i + 2] == player
-                    && line[i + 3] == Cell::Empty
-                    && line[i + 4] == player
-                    && line[i + 5] == Cell::Empty
-                {
-                    ret.half_open_threes += 1;
-                }
-                if i + 6 <= len
-                    && line[i] == opponent
-                    && line[i + 1] == player
-                    && line[i + 2] == Cell::Empty
-                    && line[i + 3] == player
-                    && line[i + 4] == player
-                    && line[i + 5] == Cell::Empty
-                {
-                    ret.half_open_threes += 1;
-                }
-                if len >= 4
-                    && i == 0
-                    && line[i] == player
-                    && line[i + 1] == player
-                    && line[i + 2] == player
-                    && line[i + 3] == Cell::Empty
-                {
-                    ret.half_open_threes += 1;
-                }
-                if len >= 4
-                    && i == len - 4
-                    && line[i] == Cell::Empty
-                    && line[i + 1] == player
-                    && line[i + 2] == player
-                    && line[i + 3] == player
-                {
-                    ret.half_open_threes += 1;
-                }
-                if i + 4 <= len
-                    && line[i] == Cell::Empty
-                    && line[i + 1] == player
-                    && line[i + 2] == player
-                    && line[i + 3] == Cell::Empty
-                {
-                    ret.open_twos += 1;
-                }
-            }
-            self.num_to_combos[len].insert(num, ret);
-            ret
+
+    #[test]
+    fn defenses_keep_the_only_block() {
+        // Black has four in a row with one end blocked, only (7, 7) stops the win by fours.
+        let mut board = Board::default();
+        for &(x, y) in &[(7, 3), (7, 2), (7, 4), (8, 4), (7, 5), (8, 5), (7, 6)] {
+            board.put(x, y);
         }
+        let ai_data = &Engine::new(&board).data;
+        let mut tss = ThreatSearch::new(&ai_data.black_board, &ai_data.white_board, VCF_NODES);
+        let moves = candidate_moves(ai_data);
+        assert!(moves.len() > 1);
+
+        assert_eq!(defenses(&mut tss, Cell::White, moves), vec![(7, 7)]);
     }
-    pub fn line<'a>(&'a self, pos: (i32, i32), dir: (i32, i32)) -> BoardLineIterator<'a> {
-        BoardLineIterator {
-            p: pos,
-            v: dir,
-            cells: &self.cells,
+
+    #[test]
+    fn hints_show_the_only_defense() {
+        // Black has four in a row with one end blocked, white to move must block at (7, 7).
+        let mut board = Board::default();
+        for &(x, y) in &[(7, 3), (7, 2), (7, 4), (8, 4), (7, 5), (8, 5), (7, 6)] {
+            board.put(x, y);
         }
+        let heat = heat_map(&Engine::new(&board).move_scores(2));
+
+        assert_eq!(heat[7][7], MAX_HEAT);
+        let warm = heat.iter().flatten().filter(|&&h| h > 0.0).count();
+        assert_eq!(warm, 1);
     }
 }
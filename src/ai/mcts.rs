@@ -0,0 +1,293 @@
+//! Monte Carlo tree search, the other way of choosing a move besides `alphabeta`.
+//!
+//! Instead of evaluating positions a few moves deep, it plays thousands of quick games from them
+//! and grows a tree towards the moves that won the most of them (UCT). Every node of the tree only
+//! tries the moves making and blocking the most threats, so the wide openings are no problem for it.
+
+use super::*;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum PlayoutPolicy {
+    /// Any move near the stones.
+    Random,
+    /// Makes fives and blocks fours, otherwise plays the most threatening of a few random moves.
+    Threats,
+}
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct MctsOptions {
+    /// Playouts per move, fewer are played if the time runs out.
+    pub playouts: usize,
+    pub time: Option<Duration>,
+    /// How much UCT tries moves it knows little about instead of the ones winning the most.
+    pub exploration: f32,
+    /// How many moves are tried in each position of the tree.
+    pub width: usize,
+    /// Playouts still going after this many moves are counted as draws.
+    pub playout_depth: usize,
+    pub policy: PlayoutPolicy,
+}
+
+impl Default for MctsOptions {
+    fn default() -> MctsOptions {
+        MctsOptions {
+            playouts: 5000,
+            time: Some(Duration::from_secs(3)),
+            exploration: 1.0,
+            width: 12,
+            playout_depth: 40,
+            policy: PlayoutPolicy::Threats,
+        }
+    }
+}
+
+impl MctsOptions {
+    /// The playouts and time of `level`, the rest as by default.
+    pub fn for_level(level: &Level) -> MctsOptions {
+        MctsOptions {
+            playouts: level.playouts,
+            time: level.limits.time,
+            ..Default::default()
+        }
+    }
+}
+
+/// The threatening policy compares this many random moves.
+const SAMPLED_MOVES: usize = 6;
+
+struct Node {
+    /// The move leading here, `None` at the root.
+    pos: Option<(usize, usize)>,
+    parent: usize,
+    children: Vec<usize>,
+    /// The moves not tried yet, the best looking last.
+    untried: Vec<(usize, usize)>,
+    visits: u32,
+    /// The points the player making `pos` got in the playouts through here, a draw is worth half.
+    score: f32,
+    /// Whether `pos` made five.
+    won: bool,
+}
+
+impl Node {
+    fn new(pos: Option<(usize, usize)>, parent: usize, untried: Vec<(usize, usize)>) -> Node {
+        Node {
+            pos,
+            parent,
+            children: vec![],
+            untried,
+            visits: 0,
+            score: 0.0,
+            won: false,
+        }
+    }
+}
+
+/// Chooses the move of the player to move by playing `options.playouts` games,
+/// `value` of the result is its winning chance.
+pub(super) fn mcts_move(ai_data: &mut AiData, options: &MctsOptions) -> Move {
+    ai_data.reseed();
+    let deadline = options.time.map(|t| Instant::now() + t);
+    let mut nodes = vec![Node::new(None, 0, tree_moves(ai_data, options.width))];
+    let mut playouts = 0;
+
+    while playouts < options.playouts && deadline.map_or(true, |d| Instant::now() < d) {
+        let mut id = 0;
+        let mut played = 0;
+        while nodes[id].untried.is_empty() && !nodes[id].children.is_empty() && !nodes[id].won {
+            id = best_child(&nodes, id, options.exploration);
+            if let Some((x, y)) = nodes[id].pos {
+                ai_data.put(x, y);
+                played += 1;
+            }
+        }
+
+        if !nodes[id].won {
+            if let Some((x, y)) = nodes[id].untried.pop() {
+                let player = ai_data.next_color();
+                ai_data.put(x, y);
+                played += 1;
+                let won = ai_data.boards(player).0.combos.get(Threat::Five) > 0;
+                let untried = if won {
+                    vec![]
+                } else {
+                    tree_moves(ai_data, options.width)
+                };
+                nodes.push(Node {
+                    won,
+                    ..Node::new(Some((x, y)), id, untried)
+                });
+                let child = nodes.len() - 1;
+                nodes[id].children.push(child);
+                id = child;
+            }
+        }
+
+        let mut mover = ai_data.next_color().opponent();
+        let winner = if nodes[id].won {
+            Some(mover)
+        } else {
+            playout(ai_data, options)
+        };
+        loop {
+            let node = &mut nodes[id];
+            node.visits += 1;
+            node.score += match winner {
+                Some(winner) if winner == mover => 1.0,
+                Some(_) => 0.0,
+                None => 0.5,
+            };
+            if id == 0 {
+                break;
+            }
+            id = node.parent;
+            mover = mover.opponent();
+        }
+
+        for _ in 0..played {
+            ai_data.undo();
+        }
+        playouts += 1;
+    }
+
+    let best = nodes[0]
+        .children
+        .iter()
+        .max_by_key(|&&child| (nodes[child].won, nodes[child].visits))
+        .map(|&child| &nodes[child]);
+    Move {
+        value: best.map_or(0.5, |node| node.score / node.visits.max(1) as f32),
+        pos: best
+            .and_then(|node| node.pos)
+            .unwrap_or((MAP_SIZE / 2, MAP_SIZE / 2)),
+        searched_count: playouts,
+    }
+}
+
+/// The child of `id` with the best upper confidence bound, or the one making five, playouts
+/// would also count slower wins as good as that.
+fn best_child(nodes: &[Node], id: usize, exploration: f32) -> usize {
+    if let Some(&won) = nodes[id].children.iter().find(|&&child| nodes[child].won) {
+        return won;
+    }
+    let log_visits = (nodes[id].visits.max(1) as f32).ln();
+    let bound = |child: usize| {
+        let node = &nodes[child];
+        let visits = node.visits.max(1) as f32;
+        node.score / visits + exploration * (log_visits / visits).sqrt()
+    };
+    let mut children = nodes[id].children.iter().cloned();
+    let first = children.next().unwrap_or(id);
+    children.fold(first, |best, child| {
+        if bound(child) > bound(best) {
+            child
+        } else {
+            best
+        }
+    })
+}
+
+/// The `width` moves making and blocking the most threats, the best ones last.
+fn tree_moves(ai_data: &mut AiData, width: usize) -> Vec<(usize, usize)> {
+    let mut scored: Vec<(f64, (usize, usize))> = candidate_moves(ai_data)
+        .into_iter()
+        .map(|m| (threat_delta(ai_data, m), m))
+        .collect();
+    scored.sort_by(|a, b| b.0.partial_cmp(&a.0).unwrap_or(Ordering::Equal));
+    scored.truncate(width);
+    scored.into_iter().rev().map(|(_, m)| m).collect()
+}
+
+/// Plays on until someone makes five, returns the winner or `None` for a draw.
+fn playout(ai_data: &mut AiData, options: &MctsOptions) -> Option<Cell> {
+    let mut winner = None;
+    let mut played = 0;
+
+    while played < options.playout_depth {
+        let moves = candidate_moves(ai_data);
+        if moves.is_empty() {
+            break;
+        }
+        let (x, y) = match options.policy {
+            PlayoutPolicy::Random => moves[ai_data.rng.below(moves.len())],
+            PlayoutPolicy::Threats => threatening_move(ai_data, moves),
+        };
+        let player = ai_data.next_color();
+        ai_data.put(x, y);
+        played += 1;
+        if ai_data.boards(player).0.combos.get(Threat::Five) > 0 {
+            winner = Some(player);
+            break;
+        }
+    }
+
+    for _ in 0..played {
+        ai_data.undo();
+    }
+    winner
+}
+
+/// When someone has a four, the move making five or blocking it, else the most threatening
+/// of a few random moves.
+fn threatening_move(ai_data: &mut AiData, mut moves: Vec<(usize, usize)>) -> (usize, usize) {
+    let fours = |board: &BoardAsNums| {
+        board.combos.get(Threat::OpenFour) + board.combos.get(Threat::HalfOpenFour)
+    };
+    let (mine, theirs) = ai_data.boards(ai_data.next_color());
+    if fours(mine) == 0 && fours(theirs) == 0 {
+        for i in 0..SAMPLED_MOVES.min(moves.len()) {
+            let j = i + ai_data.rng.below(moves.len() - i);
+            moves.swap(i, j);
+        }
+        moves.truncate(SAMPLED_MOVES);
+    }
+
+    // A five is worth the most and blocking a four is worth its weight, so threat_delta finds them.
+    let mut best = (std::f64::MIN, moves[0]);
+    for m in moves {
+        let delta = threat_delta(ai_data, m);
+        if delta > best.0 {
+            best = (delta, m);
+        }
+    }
+    best.1
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn mcts_move_after(moves: &[(usize, usize)]) -> (usize, usize) {
+        let mut engine = Engine::new(&Board::default());
+        for &pos in moves {
+            engine.apply(pos).unwrap();
+        }
+        engine.set_method(Method::Mcts(MctsOptions {
+            playouts: 300,
+            time: None,
+            ..Default::default()
+        }));
+        engine.best_move().pos
+    }
+
+    #[test]
+    fn makes_five() {
+        let moves = [
+            (7, 3),
+            (7, 2),
+            (7, 4),
+            (8, 4),
+            (7, 5),
+            (8, 5),
+            (7, 6),
+            (0, 0),
+        ];
+        assert_eq!(mcts_move_after(&moves), (7, 7));
+    }
+
+    #[test]
+    fn blocks_four() {
+        let moves = [(7, 3), (7, 2), (7, 4), (8, 4), (7, 5), (8, 5), (7, 6)];
+        assert_eq!(mcts_move_after(&moves), (7, 7));
+    }
+}
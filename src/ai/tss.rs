@@ -0,0 +1,421 @@
+//! Threat-space search: looks for wins forced by a chain of fours (VCF)
+//! or by a chain of fours and open threes (VCT).
+//!
+//! The threats are the ones the evaluator counts with the shapes of `data/shapes.txt`:
+//! a move makes a four when it adds an open or half open four, an open three when it adds
+//! an open three, and the answers to a three are the moves taking it away again.
+//! A `shapes.txt` of the user changes what is searched for along with the evaluation.
+
+use super::*;
+
+const DIRECTIONS: [(i32, i32); 4] = [(1, 0), (0, 1), (1, 1), (1, -1)];
+
+/// How far from a stone a move can still make a threat with it.
+const REACH: i32 = 4;
+
+const FOURS: [Threat; 2] = [Threat::OpenFour, Threat::HalfOpenFour];
+
+fn side(player: Cell) -> usize {
+    if player == Cell::Black {
+        0
+    } else {
+        1
+    }
+}
+
+/// The intersections up to `REACH` away from `p` along the lines through it.
+fn on_lines(p: (usize, usize)) -> Vec<(usize, usize)> {
+    let mut points = vec![];
+    for &(dx, dy) in &DIRECTIONS {
+        for i in (-REACH..=REACH).filter(|&i| i != 0) {
+            let (x, y) = (p.0 as i32 + dx * i, p.1 as i32 + dy * i);
+            if x >= 0 && y >= 0 && x < MAP_SIZE as i32 && y < MAP_SIZE as i32 {
+                points.push((x as usize, y as usize));
+            }
+        }
+    }
+    points
+}
+
+pub(super) struct ThreatSearch {
+    /// The boards counting the threats of black and of white.
+    boards: [BoardAsNums; 2],
+    nodes: usize,
+    /// The search gives up after visiting this many positions,
+    pub node_limit: usize,
+    /// or once this time has passed.
+    pub deadline: Option<Instant>,
+}
+
+impl ThreatSearch {
+    pub fn new(black: &BoardAsNums, white: &BoardAsNums, node_limit: usize) -> ThreatSearch {
+        ThreatSearch {
+            boards: [black.clone(), white.clone()],
+            nodes: 0,
+            node_limit,
+            deadline: None,
+        }
+    }
+    /// The number of positions visited so far.
+    pub fn nodes(&self) -> usize {
+        self.nodes
+    }
+    pub fn out_of_time(&self) -> bool {
+        self.deadline.map_or(false, |d| Instant::now() >= d)
+    }
+    fn gives_up(&self) -> bool {
+        self.nodes >= self.node_limit || self.out_of_time()
+    }
+    pub fn put(&mut self, p: (usize, usize), cell: Cell) {
+        for board in self.boards.iter_mut() {
+            board.set(p.0, p.1, cell);
+        }
+    }
+
+    fn cell(&self, p: (usize, usize)) -> Cell {
+        self.boards[0].cells[p.0][p.1]
+    }
+    fn count(&self, player: Cell, threats: &[Threat]) -> u32 {
+        let combos = &self.boards[side(player)].combos;
+        threats.iter().map(|&t| combos.get(t) as u32).sum()
+    }
+    fn has_five(&self, player: Cell) -> bool {
+        self.count(player, &[Threat::Five]) > 0
+    }
+    /// How many of `threats` `player` would have after playing at `p`.
+    fn count_after(&mut self, p: (usize, usize), player: Cell, threats: &[Threat]) -> u32 {
+        let board = &mut self.boards[side(player)];
+        board.set(p.0, p.1, player);
+        let count = threats.iter().map(|&t| board.combos.get(t) as u32).sum();
+        board.set(p.0, p.1, Cell::Empty);
+        count
+    }
+    fn adds(&mut self, p: (usize, usize), player: Cell, threats: &[Threat]) -> bool {
+        self.count_after(p, player, threats) > self.count(player, threats)
+    }
+    /// Marks the intersections with at least `stones` stones of `player` up to `reach` away
+    /// on one of the lines through them.
+    fn mark_close(
+        &self,
+        player: Cell,
+        stones: usize,
+        reach: usize,
+        close: &mut [[bool; MAP_SIZE]; MAP_SIZE],
+    ) {
+        let reach = reach as i32;
+        for &(dx, dy) in &DIRECTIONS {
+            let mut counts = [[0; MAP_SIZE]; MAP_SIZE];
+            for x in 0..MAP_SIZE {
+                for y in 0..MAP_SIZE {
+                    if self.cell((x, y)) != player {
+                        continue;
+                    }
+                    for i in (-reach..=reach).filter(|&i| i != 0) {
+                        let (x, y) = (x as i32 + dx * i, y as i32 + dy * i);
+                        if x >= 0 && y >= 0 && x < MAP_SIZE as i32 && y < MAP_SIZE as i32 {
+                            counts[x as usize][y as usize] += 1;
+                        }
+                    }
+                }
+            }
+            for (close, counts) in close.iter_mut().zip(&counts) {
+                for (close, &count) in close.iter_mut().zip(counts) {
+                    *close |= count >= stones;
+                }
+            }
+        }
+    }
+    /// The empty intersections where `player` would add one of `threats`. Only the ones with
+    /// enough stones of the player around them for a shape of the threats are tried.
+    fn moves_adding(&mut self, player: Cell, threats: &[Threat]) -> Vec<(usize, usize)> {
+        let mut close = [[false; MAP_SIZE]; MAP_SIZE];
+        for &t in threats {
+            let (fewest, longest) = self.boards[side(player)].spans[t as usize];
+            self.mark_close(
+                player,
+                fewest.saturating_sub(1),
+                longest.saturating_sub(1),
+                &mut close,
+            );
+        }
+        let mut moves = vec![];
+        for x in 0..MAP_SIZE {
+            for y in 0..MAP_SIZE {
+                let p = (x, y);
+                if close[p.0][p.1] && self.cell(p) == Cell::Empty && self.adds(p, player, threats) {
+                    moves.push(p);
+                }
+            }
+        }
+        moves
+    }
+
+    /// The intersections where `player` would make five.
+    pub fn gains(&mut self, player: Cell) -> Vec<(usize, usize)> {
+        self.moves_adding(player, &[Threat::Five])
+    }
+    /// The intersections where `player` would make five with a line going through `p`.
+    fn gains_through(&mut self, player: Cell, p: (usize, usize)) -> Vec<(usize, usize)> {
+        on_lines(p)
+            .into_iter()
+            .filter(|&q| self.cell(q) == Cell::Empty && self.adds(q, player, &[Threat::Five]))
+            .collect()
+    }
+    /// The intersections where `player` would make a four.
+    pub fn four_moves(&mut self, player: Cell) -> Vec<(usize, usize)> {
+        self.moves_adding(player, &FOURS)
+    }
+    /// The intersections where `player` would make an open three.
+    pub fn three_moves(&mut self, player: Cell) -> Vec<(usize, usize)> {
+        self.moves_adding(player, &[Threat::OpenThree])
+    }
+    /// The intersections that take away an open three of `player` going through `p`.
+    fn three_defenses(&mut self, player: Cell, p: (usize, usize)) -> Vec<(usize, usize)> {
+        let threes = self.count(player, &[Threat::OpenThree]);
+        let mut defenses = vec![];
+        for q in on_lines(p) {
+            if self.cell(q) != Cell::Empty {
+                continue;
+            }
+            let board = &mut self.boards[side(player)];
+            board.set(q.0, q.1, player.opponent());
+            let left = board.combos.get(Threat::OpenThree) as u32;
+            board.set(q.0, q.1, Cell::Empty);
+            if left < threes {
+                defenses.push(q);
+            }
+        }
+        defenses
+    }
+
+    /// A win for `attacker`, who is to move, made of fours only.
+    ///
+    /// The result holds the moves of both players, ending with the move that makes five
+    /// or the one that makes two fours at once.
+    pub fn vcf(&mut self, attacker: Cell, depth: u32) -> Option<Vec<(usize, usize)>> {
+        let mut line = vec![];
+        if self.vcf_rec(attacker, depth, &mut line) {
+            Some(line)
+        } else {
+            None
+        }
+    }
+    fn vcf_rec(&mut self, attacker: Cell, depth: u32, line: &mut Vec<(usize, usize)>) -> bool {
+        if self.gives_up() {
+            return false;
+        }
+        self.nodes += 1;
+        if let Some(&p) = self.gains(attacker).first() {
+            line.push(p);
+            return true;
+        }
+        let threats = self.gains(attacker.opponent());
+        if threats.len() > 1 || depth == 0 {
+            return false;
+        }
+
+        let mut candidates = self.four_moves(attacker);
+        if let Some(threat) = threats.first() {
+            candidates.retain(|p| p == threat);
+        }
+        for &m in &candidates {
+            self.put(m, attacker);
+            let gains = self.gains_through(attacker, m).len();
+            self.put(m, Cell::Empty);
+            if gains >= 2 {
+                line.push(m);
+                return true;
+            }
+        }
+
+        for m in candidates {
+            self.put(m, attacker);
+            let won = match self.gains_through(attacker, m).first() {
+                Some(&block) => {
+                    self.put(block, attacker.opponent());
+                    let len = line.len();
+                    line.extend_from_slice(&[m, block]);
+                    let won = !self.has_five(attacker.opponent())
+                        && self.vcf_rec(attacker, depth - 1, line);
+                    if !won {
+                        line.truncate(len);
+                    }
+                    self.put(block, Cell::Empty);
+                    won
+                }
+                None => false,
+            };
+            self.put(m, Cell::Empty);
+            if won {
+                return true;
+            }
+        }
+        false
+    }
+
+    /// A win for `attacker`, who is to move, made of fours and open threes.
+    ///
+    /// Only the first move of the win is returned, the answers to threes branch too much
+    /// for a single line to be useful.
+    pub fn vct(&mut self, attacker: Cell, depth: u32) -> Option<(usize, usize)> {
+        self.vct_rec(attacker, depth)
+    }
+    fn vct_rec(&mut self, attacker: Cell, depth: u32) -> Option<(usize, usize)> {
+        if self.gives_up() {
+            return None;
+        }
+        self.nodes += 1;
+        if let Some(&p) = self.gains(attacker).first() {
+            return Some(p);
+        }
+        let defender = attacker.opponent();
+        let threats = self.gains(defender);
+        if threats.len() > 1 || depth == 0 {
+            return None;
+        }
+
+        let mut candidates = self.four_moves(attacker);
+        for p in self.three_moves(attacker) {
+            if !candidates.contains(&p) {
+                candidates.push(p);
+            }
+        }
+        if let Some(threat) = threats.first() {
+            candidates.retain(|p| p == threat);
+        }
+
+        for m in candidates {
+            self.put(m, attacker);
+            let gains = self.gains_through(attacker, m);
+            let won = match gains.len() {
+                0 => {
+                    let mut replies = self.three_defenses(attacker, m);
+                    if !replies.is_empty() {
+                        for p in self.four_moves(defender) {
+                            if !replies.contains(&p) {
+                                replies.push(p);
+                            }
+                        }
+                    }
+                    !replies.is_empty() && self.refutes_none(attacker, depth, &replies)
+                }
+                1 => self.refutes_none(attacker, depth, &gains),
+                _ => true,
+            };
+            self.put(m, Cell::Empty);
+            if won {
+                return Some(m);
+            }
+        }
+        None
+    }
+    /// Whether `attacker` still wins after each of the defender's `replies`.
+    fn refutes_none(&mut self, attacker: Cell, depth: u32, replies: &[(usize, usize)]) -> bool {
+        for &r in replies {
+            self.put(r, attacker.opponent());
+            let won =
+                !self.has_five(attacker.opponent()) && self.vct_rec(attacker, depth - 1).is_some();
+            self.put(r, Cell::Empty);
+            if !won {
+                return false;
+            }
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn search(
+        black: &[(usize, usize)],
+        white: &[(usize, usize)],
+        node_limit: usize,
+    ) -> ThreatSearch {
+        let shapes = Shapes::builtin();
+        let mut tss = ThreatSearch::new(
+            &BoardAsNums::new(Cell::Black, &shapes),
+            &BoardAsNums::new(Cell::White, &shapes),
+            node_limit,
+        );
+        for &p in black {
+            tss.put(p, Cell::Black);
+        }
+        for &p in white {
+            tss.put(p, Cell::White);
+        }
+        tss
+    }
+
+    /// Black makes a four on row 7 with (7, 7) or on the diagonal with (8, 7), and once
+    /// white has blocked it the other one makes two fours, the second on column 7.
+    /// None of its threes is open at both ends, so it takes three moves.
+    const CHAIN_BLACK: [(usize, usize); 8] = [
+        (7, 4),
+        (7, 5),
+        (7, 6),
+        (5, 7),
+        (6, 7),
+        (9, 6),
+        (10, 5),
+        (11, 4),
+    ];
+    const CHAIN_WHITE: [(usize, usize); 4] = [(7, 8), (7, 2), (4, 7), (13, 2)];
+
+    #[test]
+    fn vcf_finds_a_chain_of_fours() {
+        let mut tss = search(&CHAIN_BLACK, &CHAIN_WHITE, 1000);
+        let line = tss.vcf(Cell::Black, 12).unwrap();
+
+        assert_eq!(line.len(), 3, "{:?}", line);
+        assert!(line[0] == (7, 7) || line[0] == (8, 7), "{:?}", line);
+        for (i, &p) in line.iter().enumerate() {
+            tss.put(p, if i % 2 == 0 { Cell::Black } else { Cell::White });
+        }
+        assert!(tss.gains(Cell::Black).len() >= 2);
+        assert!(tss.gains(Cell::White).is_empty());
+    }
+
+    #[test]
+    fn vcf_ends_with_a_double_four() {
+        let black = [(7, 4), (7, 5), (7, 6), (4, 7), (5, 7), (6, 7)];
+        let mut tss = search(&black, &[(7, 3), (3, 7)], 1000);
+
+        assert_eq!(tss.vcf(Cell::Black, 12), Some(vec![(7, 7)]));
+    }
+
+    #[test]
+    fn vct_makes_threes_into_fours() {
+        let black = [(7, 5), (7, 6), (5, 7), (6, 7)];
+        let mut tss = search(&black, &[(0, 0)], 10000);
+
+        assert_eq!(tss.vcf(Cell::Black, 12), None);
+        let first = tss.vct(Cell::Black, 4).unwrap();
+        assert!(tss.three_moves(Cell::Black).contains(&first), "{:?}", first);
+    }
+
+    #[test]
+    fn no_win_against_an_open_four() {
+        let white = [(10, 10), (10, 11), (10, 12), (10, 13), (7, 3), (3, 7)];
+        let black = [(7, 4), (7, 5), (7, 6), (4, 7), (5, 7), (6, 7)];
+        let mut tss = search(&black, &white, 10000);
+
+        assert_eq!(tss.vcf(Cell::Black, 12), None);
+        assert_eq!(tss.vct(Cell::Black, 4), None);
+    }
+
+    #[test]
+    fn node_limit_is_kept() {
+        let mut tss = search(&CHAIN_BLACK, &CHAIN_WHITE, 1);
+        assert_eq!(tss.vcf(Cell::Black, 12), None);
+        assert_eq!(tss.nodes(), 1);
+
+        tss.node_limit = 3;
+        assert_eq!(tss.vct(Cell::Black, 4), None);
+        assert!(tss.nodes() <= 3);
+
+        tss.node_limit = 1000;
+        assert!(tss.vcf(Cell::Black, 12).is_some());
+    }
+}
@@ -0,0 +1,176 @@
+//! Reviewing games: every move is compared to the best move the AI finds in its place,
+//! the ones giving away too much are marked as mistakes and blunders.
+
+use super::ai::Engine;
+use super::board::*;
+
+/// Values beyond this are won or lost games, an open three is worth about 1.
+const DECIDED: f32 = 5000.0;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct AnalysisOptions {
+    /// How many moves deep every move is searched, counting the move itself.
+    pub depth: u32,
+    /// A move worse than the best by this much is a mistake.
+    pub mistake: f32,
+    /// And one worse by this much is a blunder, so is every move giving away a won game
+    /// or walking into a lost one.
+    pub blunder: f32,
+}
+
+impl Default for AnalysisOptions {
+    fn default() -> AnalysisOptions {
+        AnalysisOptions {
+            depth: 3,
+            mistake: 1.0,
+            blunder: 4.0,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct MoveReview {
+    /// The number of the move counting from 1, like the ids of annotations.
+    pub id: usize,
+    pub played: (usize, usize),
+    /// The value of the played move for the player making it.
+    pub value: f32,
+    pub best: (usize, usize),
+    pub best_value: f32,
+    /// `Mistake`, `Blunder` or `None` for a good enough move.
+    pub symbol: Option<MoveSymbol>,
+}
+
+impl MoveReview {
+    /// How much worse the played move is than the best one.
+    pub fn loss(&self) -> f32 {
+        (self.best_value - self.value).max(0.0)
+    }
+}
+
+impl AnalysisOptions {
+    fn judge(&self, value: f32, best_value: f32) -> Option<MoveSymbol> {
+        let loss = best_value - value;
+        if (best_value >= DECIDED && value < DECIDED)
+            || (value <= -DECIDED && best_value > -DECIDED)
+            || loss >= self.blunder
+        {
+            Some(MoveSymbol::Blunder)
+        } else if loss >= self.mistake {
+            Some(MoveSymbol::Mistake)
+        } else {
+            None
+        }
+    }
+}
+
+/// Reviews every move of `board`, each searched `options.depth` moves deep.
+pub fn analyze(board: &Board, options: &AnalysisOptions) -> Vec<MoveReview> {
+    analyze_with(&mut Engine::new(&Board::default()), board, options)
+}
+
+/// Like `analyze` with an engine of the caller, which saves making a new one for every game.
+pub fn analyze_with(
+    engine: &mut Engine,
+    board: &Board,
+    options: &AnalysisOptions,
+) -> Vec<MoveReview> {
+    engine.sync(&Board::default());
+    let mut reviews = vec![];
+
+    for (i, &played) in board.moves().iter().enumerate() {
+        let scores = engine.move_scores(options.depth);
+        let value = match scores.iter().find(|(pos, _)| *pos == played) {
+            Some(&(_, value)) => value,
+            None => engine.move_score(played, options.depth),
+        };
+        let (mut best, mut best_value) = (played, value);
+        for &(pos, score) in &scores {
+            if score > best_value {
+                best = pos;
+                best_value = score;
+            }
+        }
+
+        reviews.push(MoveReview {
+            id: i + 1,
+            played,
+            value,
+            best,
+            best_value,
+            symbol: options.judge(value, best_value),
+        });
+        if engine.apply(played).is_err() {
+            break;
+        }
+    }
+    reviews
+}
+
+/// Notes the mistakes and blunders of `reviews` on the moves of `board`, with the better move
+/// circled. Moves already having notes keep them.
+pub fn annotate(board: &mut Board, reviews: &[MoveReview]) {
+    for review in reviews {
+        if review.symbol.is_none() || board.annotation(review.id).is_some() {
+            continue;
+        }
+        board.annotate(
+            review.id,
+            Annotation {
+                comment: format!("{} was better", point_name(review.best)),
+                symbol: review.symbol,
+                marks: vec![Mark::Circle(review.best)],
+            },
+        );
+    }
+}
+
+/// Like "2 mistakes, 1 blunder".
+pub fn summary(reviews: &[MoveReview]) -> String {
+    let count = |symbol: MoveSymbol| {
+        reviews
+            .iter()
+            .filter(|review| review.symbol == Some(symbol))
+            .count()
+    };
+    let plural = |n: usize, what: &str| format!("{} {}{}", n, what, if n == 1 { "" } else { "s" });
+    format!(
+        "{}, {}",
+        plural(count(MoveSymbol::Mistake), "mistake"),
+        plural(count(MoveSymbol::Blunder), "blunder")
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn missing_a_block_is_a_blunder() {
+        // White ignores the four of black and loses.
+        let mut board = Board::default();
+        for &(x, y) in &[
+            (7, 3),
+            (7, 2),
+            (7, 4),
+            (8, 4),
+            (7, 5),
+            (8, 5),
+            (7, 6),
+            (0, 0),
+        ] {
+            board.put(x, y);
+        }
+        let reviews = analyze(&board, &AnalysisOptions::default());
+
+        assert_eq!(reviews.len(), 8);
+        let last = &reviews[7];
+        assert_eq!(last.symbol, Some(MoveSymbol::Blunder));
+        assert_eq!(last.best, (7, 7));
+
+        annotate(&mut board, &reviews);
+        let note = board.annotation(8).unwrap();
+        assert_eq!(note.marks, vec![Mark::Circle((7, 7))]);
+        assert_eq!(summary(&reviews[7..]), "0 mistakes, 1 blunder");
+    }
+}
@@ -0,0 +1,382 @@
+//! Compact binary storage for large game collections.
+//!
+//! Layout of an archive, all integers little endian:
+//!
+//! ```text
+//! "GMKA" version:u8
+//! game*          size:u8 result:u8 move_count:u16 meta_len:u16 meta:[u8; meta_len] moves:[u8; move_count]
+//! index          offset:u64 for every game
+//! footer         index_offset:u64 game_count:u32 "GMKI"
+//! ```
+//!
+//! A move is stored in one byte as `x << 4 | y`, so boards up to 16x16 fit.
+
+extern crate serde_json;
+
+use super::board::*;
+use std::fs::File;
+use std::io;
+use std::io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+const MAGIC: &[u8; 4] = b"GMKA";
+const INDEX_MAGIC: &[u8; 4] = b"GMKI";
+const VERSION: u8 = 1;
+const FOOTER_LEN: u64 = 16;
+pub const MAX_SIZE: usize = 16;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Outcome {
+    NotFinished,
+    BlackWon,
+    WhiteWon,
+    Draw,
+}
+
+impl Outcome {
+    pub fn of(result: &GameResult) -> Outcome {
+        match result {
+            GameResult::NotFinished => Outcome::NotFinished,
+            GameResult::BlackWon(_) => Outcome::BlackWon,
+            GameResult::WhiteWon(_) => Outcome::WhiteWon,
+            GameResult::Draw => Outcome::Draw,
+        }
+    }
+    fn to_byte(self) -> u8 {
+        match self {
+            Outcome::NotFinished => 0,
+            Outcome::BlackWon => 1,
+            Outcome::WhiteWon => 2,
+            Outcome::Draw => 3,
+        }
+    }
+    fn from_byte(b: u8) -> io::Result<Outcome> {
+        match b {
+            0 => Ok(Outcome::NotFinished),
+            1 => Ok(Outcome::BlackWon),
+            2 => Ok(Outcome::WhiteWon),
+            3 => Ok(Outcome::Draw),
+            _ => Err(invalid_data(format!("unknown result {}", b))),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct GameRecord {
+    pub size: usize,
+    pub outcome: Outcome,
+    pub meta: String,
+    pub moves: Vec<(usize, usize)>,
+}
+
+impl GameRecord {
+    pub fn from_board(board: &Board, meta: &str) -> GameRecord {
+        GameRecord {
+            size: MAP_SIZE,
+            outcome: Outcome::of(&board.result()),
+            meta: meta.to_owned(),
+            moves: board.moves().clone(),
+        }
+    }
+    pub fn to_board(&self) -> io::Result<Board> {
+        if self.size != MAP_SIZE {
+            return Err(invalid_data(format!(
+                "{0}x{0} games can not be played on a {1}x{1} board",
+                self.size, MAP_SIZE
+            )));
+        }
+        let mut board = Board::default();
+        for &(x, y) in &self.moves {
+            if x >= MAP_SIZE || y >= MAP_SIZE || board.cell(x, y) != Cell::Empty {
+                return Err(invalid_data(format!("illegal move ({}, {})", x, y)));
+            }
+            board.put(x, y);
+        }
+        Ok(board)
+    }
+}
+
+fn invalid_data(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+pub struct ArchiveWriter<W: Write> {
+    out: W,
+    pos: u64,
+    offsets: Vec<u64>,
+}
+
+impl<W: Write> ArchiveWriter<W> {
+    pub fn new(mut out: W) -> io::Result<ArchiveWriter<W>> {
+        out.write_all(MAGIC)?;
+        out.write_all(&[VERSION])?;
+        Ok(ArchiveWriter {
+            out,
+            pos: MAGIC.len() as u64 + 1,
+            offsets: vec![],
+        })
+    }
+    fn write(&mut self, data: &[u8]) -> io::Result<()> {
+        self.out.write_all(data)?;
+        self.pos += data.len() as u64;
+        Ok(())
+    }
+    pub fn write_board(&mut self, board: &Board, meta: &str) -> io::Result<()> {
+        self.write_game(&GameRecord::from_board(board, meta))
+    }
+    pub fn write_game(&mut self, game: &GameRecord) -> io::Result<()> {
+        if game.size == 0 || game.size > MAX_SIZE {
+            return Err(invalid_data(format!(
+                "unsupported board size {}",
+                game.size
+            )));
+        }
+        if game.moves.len() > u16::max_value() as usize
+            || game.meta.len() > u16::max_value() as usize
+        {
+            return Err(invalid_data("game too long to archive".to_owned()));
+        }
+
+        let mut moves = Vec::with_capacity(game.moves.len());
+        for &(x, y) in &game.moves {
+            if x >= game.size || y >= game.size {
+                return Err(invalid_data(format!(
+                    "move ({}, {}) is off the board",
+                    x, y
+                )));
+            }
+            moves.push((x << 4 | y) as u8);
+        }
+
+        self.offsets.push(self.pos);
+        self.write(&[game.size as u8, game.outcome.to_byte()])?;
+        self.write(&(game.moves.len() as u16).to_le_bytes())?;
+        self.write(&(game.meta.len() as u16).to_le_bytes())?;
+        self.write(game.meta.as_bytes())?;
+        self.write(&moves)
+    }
+    /// Writes the index, the archive is not readable until this is called.
+    pub fn finish(mut self) -> io::Result<W> {
+        let index_offset = self.pos;
+        let offsets = std::mem::take(&mut self.offsets);
+        for offset in &offsets {
+            self.write(&offset.to_le_bytes())?;
+        }
+        self.write(&index_offset.to_le_bytes())?;
+        self.write(&(offsets.len() as u32).to_le_bytes())?;
+        self.write(INDEX_MAGIC)?;
+        self.out.flush()?;
+        Ok(self.out)
+    }
+}
+
+pub struct ArchiveReader<R: Read + Seek> {
+    input: R,
+    offsets: Vec<u64>,
+}
+
+fn read_array<R: Read, A: AsMut<[u8]> + Default>(input: &mut R) -> io::Result<A> {
+    let mut buf = A::default();
+    input.read_exact(buf.as_mut())?;
+    Ok(buf)
+}
+
+impl<R: Read + Seek> ArchiveReader<R> {
+    pub fn new(mut input: R) -> io::Result<ArchiveReader<R>> {
+        let magic: [u8; 4] = read_array(&mut input)?;
+        let version: [u8; 1] = read_array(&mut input)?;
+        if &magic != MAGIC {
+            return Err(invalid_data("not a game archive".to_owned()));
+        }
+        if version[0] != VERSION {
+            return Err(invalid_data(format!(
+                "unsupported archive version {}",
+                version[0]
+            )));
+        }
+
+        let end = input.seek(SeekFrom::End(0))?;
+        if end < FOOTER_LEN {
+            return Err(invalid_data("archive has no index".to_owned()));
+        }
+        input.seek(SeekFrom::Start(end - FOOTER_LEN))?;
+        let index_offset = u64::from_le_bytes(read_array(&mut input)?);
+        let count = u32::from_le_bytes(read_array(&mut input)?) as u64;
+        let magic: [u8; 4] = read_array(&mut input)?;
+        if &magic != INDEX_MAGIC || index_offset + count * 8 + FOOTER_LEN != end {
+            return Err(invalid_data("archive index is damaged".to_owned()));
+        }
+
+        input.seek(SeekFrom::Start(index_offset))?;
+        let mut offsets = Vec::with_capacity(count as usize);
+        for _ in 0..count {
+            offsets.push(u64::from_le_bytes(read_array(&mut input)?));
+        }
+
+        Ok(ArchiveReader { input, offsets })
+    }
+    pub fn len(&self) -> usize {
+        self.offsets.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.offsets.is_empty()
+    }
+    pub fn read_game(&mut self, id: usize) -> io::Result<GameRecord> {
+        let offset = *self
+            .offsets
+            .get(id)
+            .ok_or_else(|| invalid_data(format!("no game #{}", id)))?;
+        self.input.seek(SeekFrom::Start(offset))?;
+
+        let [size, outcome]: [u8; 2] = read_array(&mut self.input)?;
+        let move_count = u16::from_le_bytes(read_array(&mut self.input)?) as usize;
+        let meta_len = u16::from_le_bytes(read_array(&mut self.input)?) as usize;
+
+        let mut meta = vec![0; meta_len];
+        self.input.read_exact(&mut meta)?;
+        let mut moves = vec![0; move_count];
+        self.input.read_exact(&mut moves)?;
+
+        Ok(GameRecord {
+            size: size as usize,
+            outcome: Outcome::from_byte(outcome)?,
+            meta: String::from_utf8(meta).map_err(|e| invalid_data(e.to_string()))?,
+            moves: moves
+                .into_iter()
+                .map(|m| ((m >> 4) as usize, (m & 15) as usize))
+                .collect(),
+        })
+    }
+    pub fn read_board(&mut self, id: usize) -> io::Result<Board> {
+        self.read_game(id)?.to_board()
+    }
+    pub fn games(&mut self) -> impl Iterator<Item = io::Result<GameRecord>> + '_ {
+        (0..self.len()).map(move |id| self.read_game(id))
+    }
+}
+
+pub fn open(path: &Path) -> io::Result<ArchiveReader<BufReader<File>>> {
+    ArchiveReader::new(BufReader::new(File::open(path)?))
+}
+
+pub fn create(path: &Path) -> io::Result<ArchiveWriter<BufWriter<File>>> {
+    ArchiveWriter::new(BufWriter::new(File::create(path)?))
+}
+
+/// Converts a `games.txt` style save file, returns the number of converted and skipped games.
+pub fn convert_saves(saves: &Path, archive: &Path) -> io::Result<(usize, usize)> {
+    let mut writer = create(archive)?;
+    let (mut converted, mut skipped) = (0, 0);
+
+    for (header, moves) in read_saves(saves)? {
+        let mut board = Board::default();
+        if board.load_from_string(&moves).is_ok() {
+            writer.write_board(&board, header.trim())?;
+            converted += 1;
+        } else {
+            skipped += 1;
+        }
+    }
+
+    writer.finish()?;
+    Ok((converted, skipped))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::io::Cursor;
+
+    fn game(id: usize) -> GameRecord {
+        GameRecord {
+            size: MAP_SIZE,
+            outcome: [Outcome::NotFinished, Outcome::BlackWon, Outcome::Draw][id % 3],
+            meta: format!("game {}", id),
+            moves: (0..id).map(|i| (i, (i * 7 + id) % MAP_SIZE)).collect(),
+        }
+    }
+
+    fn archive(count: usize) -> Vec<u8> {
+        let mut writer = ArchiveWriter::new(vec![]).unwrap();
+        for id in 0..count {
+            writer.write_game(&game(id)).unwrap();
+        }
+        writer.finish().unwrap()
+    }
+
+    #[test]
+    fn games_read_back_as_written() {
+        let mut reader = ArchiveReader::new(Cursor::new(archive(10))).unwrap();
+        assert_eq!(reader.len(), 10);
+        let games: Vec<_> = reader.games().map(Result::unwrap).collect();
+        assert_eq!(games, (0..10).map(game).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn empty_archive_has_no_games() {
+        let mut reader = ArchiveReader::new(Cursor::new(archive(0))).unwrap();
+        assert!(reader.is_empty());
+        assert!(reader.read_game(0).is_err());
+    }
+
+    #[test]
+    fn index_reads_any_game_directly() {
+        let mut reader = ArchiveReader::new(Cursor::new(archive(10))).unwrap();
+        for &id in &[7, 2, 9, 0, 7] {
+            assert_eq!(reader.read_game(id).unwrap(), game(id));
+        }
+        assert!(reader.read_game(10).is_err());
+    }
+
+    #[test]
+    fn bad_magic_is_rejected() {
+        let mut data = archive(3);
+        data[0] = b'X';
+        let e = ArchiveReader::new(Cursor::new(data)).err().unwrap();
+        assert_eq!(e.kind(), io::ErrorKind::InvalidData);
+
+        let mut data = archive(3);
+        let len = data.len();
+        data[len - 1] = b'X';
+        assert!(ArchiveReader::new(Cursor::new(data)).is_err());
+    }
+
+    #[test]
+    fn truncated_archive_is_rejected() {
+        let data = archive(3);
+        for len in &[0, 3, MAGIC.len() + 1, data.len() / 2, data.len() - 1] {
+            let truncated = data[..*len].to_vec();
+            assert!(
+                ArchiveReader::new(Cursor::new(truncated)).is_err(),
+                "{} bytes",
+                len
+            );
+        }
+    }
+
+    #[test]
+    fn saves_convert_without_broken_games() {
+        let dir = std::env::temp_dir().join(format!("gomoku-archive-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let saves = dir.join("games.txt");
+        let archive = dir.join("games.gma");
+        fs::write(
+            &saves,
+            "first\n[[7,7],[7,8],[8,8]]\nbroken\n[[7,7],[7,7]]\nsecond\n[[0,0]]\n",
+        )
+        .unwrap();
+
+        let converted = convert_saves(&saves, &archive);
+        let read =
+            open(&archive).and_then(|mut reader| reader.games().collect::<io::Result<Vec<_>>>());
+        fs::remove_dir_all(&dir).unwrap();
+
+        assert_eq!(converted.unwrap(), (2, 1));
+        let games = read.unwrap();
+        assert_eq!(games[0].meta, "first");
+        assert_eq!(games[0].moves, vec![(7, 7), (7, 8), (8, 8)]);
+        assert_eq!(games[1].meta, "second");
+        assert_eq!(games[1].moves, vec![(0, 0)]);
+    }
+}
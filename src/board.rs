@@ -4,10 +4,14 @@ extern crate glui_proc;
 extern crate rand;
 extern crate serde_json;
 use self::chrono::{Datelike, Timelike};
-use super::gamestate::PlayerInt;
+use super::gamestate::{PlayerInt, PLAYER_CHOICES};
 use std::collections::HashMap;
-use std::fs::OpenOptions;
+use std::fmt;
+use std::fs;
+use std::fs::File;
+use std::io;
 use std::io::Write;
+use std::path::Path;
 
 pub const MAP_SIZE: usize = 15;
 
@@ -37,9 +41,35 @@ impl Cell {
 #[derive(Default, Debug, Clone, PartialEq)]
 pub struct Board {
     cells: [[Cell; MAP_SIZE]; MAP_SIZE],
+    /// Drawn as a red overlay, from 0 to 1, e.g. how much the AI likes each move.
+    /// It is about the current position, so it is cleared by every move and undo.
     pub heat: [[f32; MAP_SIZE]; MAP_SIZE],
     moves: Vec<(usize, usize)>,
     redo_stack: Vec<(usize, usize)>,
+    annotations: HashMap<usize, Annotation>,
+    /// Who played black and white, e.g. `Human` and `AI (Hard)`.
+    players: Option<(String, String)>,
+    /// The level and style of the AI playing black and white, `None` for humans.
+    ais: [Option<AiSetup>; 2],
+}
+
+/// The level and style an AI plays with, by name.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct AiSetup {
+    pub level: String,
+    pub style: String,
+}
+
+impl AiSetup {
+    fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({ "level": self.level, "style": self.style })
+    }
+    fn from_json(json: &serde_json::Value) -> Option<AiSetup> {
+        Some(AiSetup {
+            level: json["level"].as_str()?.to_owned(),
+            style: json["style"].as_str().unwrap_or_default().to_owned(),
+        })
+    }
 }
 
 #[derive(Debug, Eq, PartialEq, Clone)]
@@ -77,6 +107,175 @@ impl GameResult {
     }
 }
 
+/// The number of ways of turning and mirroring the board, counting leaving it as it is.
+pub const SYMMETRIES: usize = 8;
+
+/// Turns or mirrors an intersection the `id`-th way, the first four are rotations.
+pub fn symmetry(id: usize, (x, y): (usize, usize)) -> (usize, usize) {
+    let m = MAP_SIZE - 1;
+    match id {
+        0 => (x, y),
+        1 => (y, m - x),
+        2 => (m - x, m - y),
+        3 => (m - y, x),
+        4 => (x, m - y),
+        5 => (m - x, y),
+        6 => (y, x),
+        _ => (m - y, m - x),
+    }
+}
+
+/// The symmetry undoing the `id`-th one.
+pub fn inverse_symmetry(id: usize) -> usize {
+    match id {
+        1 => 3,
+        3 => 1,
+        id => id,
+    }
+}
+
+/// The name of an intersection like `h8`, columns are lettered from the left, rows numbered from the bottom.
+pub fn point_name(p: (usize, usize)) -> String {
+    format!("{}{}", (b'a' + p.1 as u8) as char, MAP_SIZE - p.0)
+}
+
+pub fn parse_point(name: &str) -> Option<(usize, usize)> {
+    let name = name.trim().to_lowercase();
+    let mut chars = name.chars();
+    let col = chars.next()? as usize;
+    let row: usize = chars.as_str().parse().ok()?;
+
+    if col < 'a' as usize || col >= 'a' as usize + MAP_SIZE || row == 0 || row > MAP_SIZE {
+        return None;
+    }
+    Some((MAP_SIZE - row, col - 'a' as usize))
+}
+
+/// A symbol drawn on the board to illustrate a position.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Mark {
+    Triangle((usize, usize)),
+    Square((usize, usize)),
+    Circle((usize, usize)),
+    Letter((usize, usize), String),
+    Arrow((usize, usize), (usize, usize)),
+}
+
+impl Mark {
+    /// Parses marks like `triangle:h8`, `letter:h8:A` or `arrow:h8:k11`.
+    pub fn parse(s: &str) -> Option<Mark> {
+        let parts: Vec<&str> = s.split(':').collect();
+        let point = |i: usize| parts.get(i).and_then(|p| parse_point(p));
+
+        match (parts[0], parts.len()) {
+            ("triangle", 2) => Some(Mark::Triangle(point(1)?)),
+            ("square", 2) => Some(Mark::Square(point(1)?)),
+            ("circle", 2) => Some(Mark::Circle(point(1)?)),
+            ("letter", 3) if !parts[2].is_empty() => {
+                Some(Mark::Letter(point(1)?, parts[2].to_owned()))
+            }
+            ("arrow", 3) => Some(Mark::Arrow(point(1)?, point(2)?)),
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for Mark {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Mark::Triangle(p) => write!(f, "triangle:{}", point_name(*p)),
+            Mark::Square(p) => write!(f, "square:{}", point_name(*p)),
+            Mark::Circle(p) => write!(f, "circle:{}", point_name(*p)),
+            Mark::Letter(p, text) => write!(f, "letter:{}:{}", point_name(*p), text),
+            Mark::Arrow(from, to) => write!(f, "arrow:{}:{}", point_name(*from), point_name(*to)),
+        }
+    }
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum MoveSymbol {
+    Good,
+    Mistake,
+    Brilliant,
+    Blunder,
+    Interesting,
+    Dubious,
+}
+
+impl MoveSymbol {
+    pub const ALL: [MoveSymbol; 6] = [
+        MoveSymbol::Good,
+        MoveSymbol::Mistake,
+        MoveSymbol::Brilliant,
+        MoveSymbol::Blunder,
+        MoveSymbol::Interesting,
+        MoveSymbol::Dubious,
+    ];
+    pub fn as_str(self) -> &'static str {
+        match self {
+            MoveSymbol::Good => "!",
+            MoveSymbol::Mistake => "?",
+            MoveSymbol::Brilliant => "!!",
+            MoveSymbol::Blunder => "??",
+            MoveSymbol::Interesting => "!?",
+            MoveSymbol::Dubious => "?!",
+        }
+    }
+    pub fn parse(s: &str) -> Option<MoveSymbol> {
+        MoveSymbol::ALL
+            .iter()
+            .cloned()
+            .find(|sym| sym.as_str() == s)
+    }
+    /// The symbol after `symbol` when cycling through all of them, `None` comes after the last.
+    pub fn cycle(symbol: Option<MoveSymbol>) -> Option<MoveSymbol> {
+        match symbol {
+            None => Some(MoveSymbol::ALL[0]),
+            Some(s) => {
+                let id = MoveSymbol::ALL.iter().position(|a| *a == s).unwrap_or(0);
+                MoveSymbol::ALL.get(id + 1).cloned()
+            }
+        }
+    }
+}
+
+/// Notes about a move: a comment, an evaluation symbol and marks on the board.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct Annotation {
+    pub comment: String,
+    pub symbol: Option<MoveSymbol>,
+    pub marks: Vec<Mark>,
+}
+
+impl Annotation {
+    pub fn is_empty(&self) -> bool {
+        self.comment.is_empty() && self.symbol.is_none() && self.marks.is_empty()
+    }
+    fn to_json(&self) -> serde_json::Value {
+        serde_json::json!({
+            "comment": self.comment,
+            "symbol": self.symbol.map(MoveSymbol::as_str),
+            "marks": self.marks.iter().map(Mark::to_string).collect::<Vec<String>>(),
+        })
+    }
+    /// Reads an annotation written by `to_json`, parts that can not be understood are left out.
+    fn from_json(json: &serde_json::Value) -> Annotation {
+        Annotation {
+            comment: json["comment"].as_str().unwrap_or_default().to_owned(),
+            symbol: json["symbol"].as_str().and_then(MoveSymbol::parse),
+            marks: json["marks"]
+                .as_array()
+                .map(|marks| {
+                    marks
+                        .iter()
+                        .filter_map(|m| m.as_str().and_then(Mark::parse))
+                        .collect()
+                })
+                .unwrap_or_default(),
+        }
+    }
+}
+
 pub struct BoardLineIterator<'a> {
     pub p: (i32, i32),
     pub v: (i32, i32),
@@ -150,9 +349,12 @@ impl Board {
     pub fn put_mayredo(&mut self, x: usize, y: usize, clear_redo: bool) -> GameResult {
         self.cells[x][y] = self.next_color();
         self.moves.push((x, y));
+        self.heat = Default::default();
 
         if clear_redo {
             self.redo_stack = vec![];
+            let n = self.moves.len();
+            self.annotations.retain(|id, _| *id < n);
         }
 
         self.result()
@@ -164,6 +366,7 @@ impl Board {
         if let Some(p) = self.moves.pop() {
             self.cells[p.0][p.1] = Cell::Empty;
             self.redo_stack.push(p);
+            self.heat = Default::default();
         }
     }
     pub fn redo_step(&mut self) -> Option<(usize, usize)> {
@@ -238,30 +441,133 @@ impl Board {
         }
         res
     }
-    pub fn load_from_string(&mut self, moves: &str) -> serde_json::Result<()> {
-        let moves: Vec<(usize, usize)> = serde_json::from_str(moves)?;
+    /// The notes of the `id`-th move, counting from 1 like the move numbers on the board.
+    pub fn annotation(&self, id: usize) -> Option<&Annotation> {
+        self.annotations.get(&id)
+    }
+    /// The notes of the latest move, the one being looked at while stepping through a game.
+    pub fn current_annotation(&self) -> Option<&Annotation> {
+        self.annotation(self.moves.len())
+    }
+    pub fn annotate(&mut self, id: usize, annotation: Annotation) {
+        if annotation.is_empty() {
+            self.annotations.remove(&id);
+        } else {
+            self.annotations.insert(id, annotation);
+        }
+    }
+    fn annotations_json(&self) -> serde_json::Value {
+        self.annotations
+            .iter()
+            .map(|(id, a)| (id.to_string(), a.to_json()))
+            .collect::<serde_json::Map<String, serde_json::Value>>()
+            .into()
+    }
+    fn load_annotations(&mut self, json: &serde_json::Value) {
+        if let Some(notes) = json.as_object() {
+            for (id, note) in notes {
+                if let Ok(id) = id.parse() {
+                    self.annotate(id, Annotation::from_json(note));
+                }
+            }
+        }
+    }
+    pub fn players(&self) -> Option<&(String, String)> {
+        self.players.as_ref()
+    }
+    pub fn set_players(&mut self, black: String, white: String) {
+        self.players = Some((black, white));
+    }
+    pub fn ais(&self) -> &[Option<AiSetup>; 2] {
+        &self.ais
+    }
+    pub fn set_ais(&mut self, ais: [Option<AiSetup>; 2]) {
+        self.ais = ais;
+    }
+    fn ais_json(&self) -> serde_json::Value {
+        serde_json::json!(self
+            .ais
+            .iter()
+            .map(|ai| ai.as_ref().map(AiSetup::to_json))
+            .collect::<Vec<_>>())
+    }
+    fn load_ais(&mut self, json: &serde_json::Value) {
+        for (side, ai) in self.ais.iter_mut().enumerate() {
+            *ai = AiSetup::from_json(&json[side]);
+        }
+    }
+    /// The move list as stored in save files, along with the annotations, players and AIs if known.
+    pub fn to_save_string(&self) -> String {
+        let has_ais = self.ais.iter().any(Option::is_some);
+        if self.annotations.is_empty() && self.players.is_none() && !has_ais {
+            return serde_json::to_string(&self.moves).unwrap_or_default();
+        }
 
-        *self = Board::default();
-        for m in moves.iter() {
-            self.put(m.0, m.1);
+        let mut json = serde_json::json!({ "moves": self.moves });
+        if !self.annotations.is_empty() {
+            json["notes"] = self.annotations_json();
         }
+        if let Some((black, white)) = &self.players {
+            json["players"] = serde_json::json!([black, white]);
+        }
+        if has_ais {
+            json["ai"] = self.ais_json();
+        }
+        json.to_string()
+    }
+    /// Loads a move list written by `to_save_string`, moves off the board or on taken
+    /// intersections are reported instead of played.
+    pub fn load_from_string(&mut self, moves: &str) -> Result<(), String> {
+        let json: serde_json::Value = serde_json::from_str(moves).map_err(|e| e.to_string())?;
+        let moves: Vec<(usize, usize)> = if json.is_object() {
+            serde_json::from_value(json["moves"].clone())
+        } else {
+            serde_json::from_value(json.clone())
+        }
+        .map_err(|e| e.to_string())?;
+
+        let mut board = Board::default();
+        for (i, m) in moves.iter().enumerate() {
+            if m.0 >= MAP_SIZE || m.1 >= MAP_SIZE {
+                return Err(format!(
+                    "move {} ({}, {}) is off the board",
+                    i + 1,
+                    m.0,
+                    m.1
+                ));
+            }
+            if board.cells[m.0][m.1] != Cell::Empty {
+                return Err(format!("move {} ({}, {}) is played twice", i + 1, m.0, m.1));
+            }
+            board.put(m.0, m.1);
+        }
+        board.load_annotations(&json["notes"]);
+        if let (Some(black), Some(white)) =
+            (json["players"][0].as_str(), json["players"][1].as_str())
+        {
+            board.set_players(black.to_owned(), white.to_owned());
+        }
+        board.load_ais(&json["ai"]);
 
+        *self = board;
         Ok(())
     }
-    pub fn append_game_to_saves(&self) {
-        let str = serde_json::to_string(&self.moves).unwrap_or_default();
+    pub fn append_game_to_saves(&self, path: &Path) -> io::Result<()> {
+        let str = self.to_save_string();
         let now = chrono::Local::now();
         let (is_pm, hour) = now.hour12();
         let (_, year) = now.year_ce();
 
-        let mut file = OpenOptions::new()
-            .create(true)
-            .append(true)
-            .open("data/games.txt")
-            .unwrap();
+        let mut contents = match fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(e),
+        };
+        if !contents.is_empty() && !contents.ends_with('\n') {
+            contents.push('\n');
+        }
 
-        write!(
-            file,
+        contents += &format!(
             "{}-{:02}-{:02} {:02}:{:02}:{:02} \n{}\n",
             year,
             now.month(),
@@ -270,7 +576,114 @@ impl Board {
             now.minute(),
             now.second(),
             str,
-        )
-        .unwrap();
+        );
+
+        write_atomic(path, contents.as_bytes())
+    }
+    /// Stores the game in the resume slot at `path`, so it can be continued after a restart.
+    pub fn save_resume(&self, path: &Path, players: (PlayerInt, PlayerInt)) -> io::Result<()> {
+        let resume = serde_json::json!({
+            "players": [players.0.to_string(), players.1.to_string()],
+            "ai": self.ais_json(),
+            "moves": self.moves,
+            "redo": self.redo_stack,
+            "notes": self.annotations_json(),
+        });
+
+        write_atomic(path, resume.to_string().as_bytes())
+    }
+    /// Restores the game last stored with `save_resume`, along with its players and AIs.
+    pub fn load_resume(path: &Path) -> Option<(Board, (PlayerInt, PlayerInt))> {
+        let contents = fs::read_to_string(path).ok()?;
+        let resume: serde_json::Value = serde_json::from_str(&contents).ok()?;
+
+        let player = |i: usize| PlayerInt::from_string(resume["players"][i].as_str()?);
+        let players = (player(0)?, player(1)?);
+        if !PLAYER_CHOICES.contains(&players) {
+            return None;
+        }
+        let moves: Vec<(usize, usize)> = serde_json::from_value(resume["moves"].clone()).ok()?;
+        let redo: Vec<(usize, usize)> = serde_json::from_value(resume["redo"].clone()).ok()?;
+
+        let mut board = Board::default();
+        for m in moves.iter().chain(redo.iter().rev()) {
+            if m.0 >= MAP_SIZE || m.1 >= MAP_SIZE || board.cells[m.0][m.1] != Cell::Empty {
+                return None;
+            }
+            board.put(m.0, m.1);
+        }
+        for _ in 0..redo.len() {
+            board.undo();
+        }
+        if board.redo_stack != redo {
+            return None;
+        }
+        board.load_annotations(&resume["notes"]);
+        board.load_ais(&resume["ai"]);
+
+        Some((board, players))
+    }
+    pub fn clear_resume(path: &Path) -> io::Result<()> {
+        match fs::remove_file(path) {
+            Err(ref e) if e.kind() == io::ErrorKind::NotFound => Ok(()),
+            r => r,
+        }
+    }
+}
+
+/// Reads the header and move list line pairs of a save file like `games.txt`.
+pub fn read_saves(path: &Path) -> io::Result<Vec<(String, String)>> {
+    Ok(parse_saves(&fs::read_to_string(path)?))
+}
+
+/// The header and move list pairs of the contents of a save file. Move lists are told apart
+/// from headers by being JSON, so a missing or extra line only breaks the game it is in:
+/// a header without a move list gets an empty one, a move list without a header an empty header.
+pub fn parse_saves(contents: &str) -> Vec<(String, String)> {
+    let mut games = vec![];
+    let mut header: Option<&str> = None;
+
+    for line in contents.lines().filter(|line| !line.trim().is_empty()) {
+        let trimmed = line.trim_start();
+        if trimmed.starts_with('[') || trimmed.starts_with('{') {
+            games.push((
+                header.take().unwrap_or_default().to_owned(),
+                line.to_owned(),
+            ));
+        } else if let Some(header) = header.replace(line) {
+            games.push((header.to_owned(), String::new()));
+        }
+    }
+    if let Some(header) = header {
+        games.push((header.to_owned(), String::new()));
+    }
+    games
+}
+
+/// Replaces the file at `path` with `data` without ever leaving a partially written file behind.
+///
+/// The data goes to a temporary file next to the target first, which is then renamed over it.
+pub fn write_atomic(path: &Path, data: &[u8]) -> io::Result<()> {
+    if let Some(dir) = path.parent() {
+        if !dir.as_os_str().is_empty() {
+            fs::create_dir_all(dir)?;
+        }
+    }
+
+    let mut tmp_name = path.file_name().unwrap_or_default().to_owned();
+    tmp_name.push(".tmp");
+    let tmp_path = path.with_file_name(tmp_name);
+
+    let written = File::create(&tmp_path).and_then(|mut file| {
+        file.write_all(data)?;
+        file.sync_all()
+    });
+
+    match written.and_then(|_| fs::rename(&tmp_path, path)) {
+        Ok(()) => Ok(()),
+        Err(e) => {
+            let _ = fs::remove_file(&tmp_path);
+            Err(e)
+        }
     }
 }
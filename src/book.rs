@@ -0,0 +1,343 @@
+//! An opening book: how the moves played from the first positions of a game collection turned out.
+//!
+//! Positions are looked up by a key that is the same for all eight turned and mirrored copies
+//! of them, so a game teaches the book about its symmetric copies as well. The key is the smallest
+//! of the Zobrist hashes (`ai::zobrist`) of the copies, and moves are stored as they are in that copy.
+//!
+//! Layout of a book file, all integers little endian, entries sorted by key:
+//!
+//! ```text
+//! "GMKB" version:u8 depth:u8 entry_count:u32
+//! entry*         key:u64 move:u8 games:u16 wins:u16 draws:u16
+//! ```
+//!
+//! A move is stored in one byte as `x << 4 | y` like in archives, the results are counted for
+//! the player making the move and stop growing at 65535.
+
+use super::ai::zobrist;
+use super::archive::{self, Outcome};
+use super::board::*;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io;
+use std::io::{BufReader, Read, Write};
+use std::path::Path;
+
+const MAGIC: &[u8; 4] = b"GMKB";
+const VERSION: u8 = 1;
+/// How many moves of every game go into the book unless told otherwise.
+pub const DEFAULT_DEPTH: usize = 12;
+/// A move is only played from the book if it was played in this many games.
+pub const MIN_GAMES: u32 = 3;
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct BookMove {
+    pub pos: (usize, usize),
+    pub games: u32,
+    /// The games won by the player making the move.
+    pub wins: u32,
+    pub draws: u32,
+}
+
+impl BookMove {
+    /// The share of the points the move got, as if it had also won and lost a game more,
+    /// so a move played once is not trusted too much.
+    pub fn score(&self) -> f32 {
+        (self.wins as f32 + self.draws as f32 / 2.0 + 1.0) / (self.games as f32 + 2.0)
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Book {
+    depth: usize,
+    positions: HashMap<u64, Vec<BookMove>>,
+}
+
+/// The hash of the position after `moves` in all its symmetric copies.
+fn hashes(moves: &[(usize, usize)]) -> [u64; SYMMETRIES] {
+    let mut hashes = [0; SYMMETRIES];
+    for (i, &pos) in moves.iter().enumerate() {
+        add_stone(&mut hashes, pos, i);
+    }
+    hashes
+}
+
+fn add_stone(hashes: &mut [u64; SYMMETRIES], pos: (usize, usize), id: usize) {
+    let cell = if id % 2 == 0 {
+        Cell::Black
+    } else {
+        Cell::White
+    };
+    for (s, hash) in hashes.iter_mut().enumerate() {
+        let (x, y) = symmetry(s, pos);
+        *hash ^= zobrist(x, y, cell);
+    }
+}
+
+/// The key of a position with the given hashes and the symmetries turning it into the copy the key belongs to.
+fn key(hashes: &[u64; SYMMETRIES]) -> (u64, Vec<usize>) {
+    let key = *hashes.iter().min().unwrap_or(&0);
+    let symmetries = (0..SYMMETRIES).filter(|&s| hashes[s] == key).collect();
+    (key, symmetries)
+}
+
+fn invalid_data(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+fn read_array<R: Read, A: AsMut<[u8]> + Default>(input: &mut R) -> io::Result<A> {
+    let mut buf = A::default();
+    input.read_exact(buf.as_mut())?;
+    Ok(buf)
+}
+
+impl Book {
+    /// An empty book learning the first `depth` moves of the games added to it.
+    pub fn new(depth: usize) -> Book {
+        Book {
+            depth: depth.min(u8::max_value() as usize),
+            positions: HashMap::new(),
+        }
+    }
+    pub fn depth(&self) -> usize {
+        self.depth
+    }
+    /// The number of positions in the book.
+    pub fn len(&self) -> usize {
+        self.positions.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.positions.is_empty()
+    }
+
+    /// Counts the result of a game for its first moves, returns false for unfinished games, they are left out.
+    pub fn add_game(&mut self, moves: &[(usize, usize)], outcome: Outcome) -> bool {
+        // Black makes the even moves, white the odd ones.
+        let winner = match outcome {
+            Outcome::BlackWon => Some(0),
+            Outcome::WhiteWon => Some(1),
+            Outcome::Draw => None,
+            Outcome::NotFinished => return false,
+        };
+        let mut hashes = [0; SYMMETRIES];
+        for (i, &pos) in moves.iter().take(self.depth).enumerate() {
+            let (key, symmetries) = key(&hashes);
+            let stored = symmetries
+                .iter()
+                .map(|&s| symmetry(s, pos))
+                .min()
+                .unwrap_or(pos);
+
+            let entries = self.positions.entry(key).or_default();
+            let id = match entries.iter().position(|m| m.pos == stored) {
+                Some(id) => id,
+                None => {
+                    entries.push(BookMove {
+                        pos: stored,
+                        games: 0,
+                        wins: 0,
+                        draws: 0,
+                    });
+                    entries.len() - 1
+                }
+            };
+            let entry = &mut entries[id];
+            entry.games += 1;
+            match winner {
+                None => entry.draws += 1,
+                Some(winner) if winner == i % 2 => entry.wins += 1,
+                Some(_) => {}
+            }
+
+            add_stone(&mut hashes, pos, i);
+        }
+        true
+    }
+
+    /// The moves played in the position after `moves`, as they are on this board. When the position is
+    /// symmetric, only one of the symmetric moves is given.
+    pub fn moves(&self, moves: &[(usize, usize)]) -> Vec<BookMove> {
+        let (key, symmetries) = key(&hashes(moves));
+        let back = inverse_symmetry(symmetries[0]);
+        self.positions
+            .get(&key)
+            .map(|entries| {
+                entries
+                    .iter()
+                    .map(|m| BookMove {
+                        pos: symmetry(back, m.pos),
+                        ..*m
+                    })
+                    .collect()
+            })
+            .unwrap_or_default()
+    }
+    /// The move with the best score among the ones played at least `MIN_GAMES` times after `moves`,
+    /// more games decide between equal scores.
+    pub fn best_move(&self, moves: &[(usize, usize)]) -> Option<(usize, usize)> {
+        if moves.len() >= self.depth {
+            return None;
+        }
+        self.moves(moves)
+            .into_iter()
+            .filter(|m| m.games >= MIN_GAMES && !moves.contains(&m.pos))
+            .max_by(|a, b| {
+                a.score()
+                    .partial_cmp(&b.score())
+                    .unwrap_or(std::cmp::Ordering::Equal)
+                    .then(a.games.cmp(&b.games))
+                    .then(b.pos.cmp(&a.pos))
+            })
+            .map(|m| m.pos)
+    }
+
+    pub fn write_to<W: Write>(&self, out: &mut W) -> io::Result<()> {
+        let mut entries: Vec<(u64, &BookMove)> = self
+            .positions
+            .iter()
+            .flat_map(|(&key, moves)| moves.iter().map(move |m| (key, m)))
+            .collect();
+        entries.sort_by_key(|&(key, m)| (key, m.pos));
+        if entries.len() > u32::max_value() as usize {
+            return Err(invalid_data("too many positions for a book".to_owned()));
+        }
+
+        out.write_all(MAGIC)?;
+        out.write_all(&[VERSION, self.depth as u8])?;
+        out.write_all(&(entries.len() as u32).to_le_bytes())?;
+        let count = |n: u32| (n.min(u16::max_value() as u32) as u16).to_le_bytes();
+        for (key, m) in entries {
+            out.write_all(&key.to_le_bytes())?;
+            out.write_all(&[(m.pos.0 << 4 | m.pos.1) as u8])?;
+            out.write_all(&count(m.games))?;
+            out.write_all(&count(m.wins))?;
+            out.write_all(&count(m.draws))?;
+        }
+        out.flush()
+    }
+    pub fn read_from<R: Read>(input: &mut R) -> io::Result<Book> {
+        let magic: [u8; 4] = read_array(input)?;
+        if &magic != MAGIC {
+            return Err(invalid_data("not an opening book".to_owned()));
+        }
+        let [version, depth]: [u8; 2] = read_array(input)?;
+        if version != VERSION {
+            return Err(invalid_data(format!(
+                "unsupported book version {}",
+                version
+            )));
+        }
+
+        let mut book = Book::new(depth as usize);
+        let count = u32::from_le_bytes(read_array(input)?);
+        for _ in 0..count {
+            let key = u64::from_le_bytes(read_array(input)?);
+            let [pos]: [u8; 1] = read_array(input)?;
+            let mut count =
+                || -> io::Result<u32> { Ok(u16::from_le_bytes(read_array(input)?) as u32) };
+            let m = BookMove {
+                pos: ((pos >> 4) as usize, (pos & 15) as usize),
+                games: count()?,
+                wins: count()?,
+                draws: count()?,
+            };
+            if m.pos.0 >= MAP_SIZE || m.pos.1 >= MAP_SIZE {
+                return Err(invalid_data(format!(
+                    "move ({}, {}) is off the board",
+                    m.pos.0, m.pos.1
+                )));
+            }
+            book.positions.entry(key).or_default().push(m);
+        }
+        Ok(book)
+    }
+    /// Writes the book to `path`, replacing the old one only once the new one is complete.
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        let mut data = vec![];
+        self.write_to(&mut data)?;
+        write_atomic(path, &data)
+    }
+    pub fn load(path: &Path) -> io::Result<Book> {
+        Book::read_from(&mut BufReader::new(File::open(path)?))
+    }
+    /// Adds the finished games among `games`, as returned by `read_saves`, returns the number of games added.
+    pub fn add_saves(&mut self, games: &[(String, String)]) -> usize {
+        let mut added = 0;
+        for (_, moves) in games {
+            let mut board = Board::default();
+            if board.load_from_string(moves).is_ok()
+                && self.add_game(board.moves(), Outcome::of(&board.result()))
+            {
+                added += 1;
+            }
+        }
+        added
+    }
+    /// Adds the finished games of an archive, returns the number of games added.
+    pub fn add_archive(&mut self, path: &Path) -> io::Result<usize> {
+        let mut added = 0;
+        for game in archive::open(path)?.games() {
+            let game = game?;
+            if game.size == MAP_SIZE && self.add_game(&game.moves, game.outcome) {
+                added += 1;
+            }
+        }
+        Ok(added)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const GAME: [(usize, usize); 9] = [
+        (7, 7),
+        (7, 8),
+        (6, 6),
+        (8, 8),
+        (5, 5),
+        (4, 4),
+        (8, 6),
+        (6, 8),
+        (9, 5),
+    ];
+
+    #[test]
+    fn symmetric_games_are_folded() {
+        let mut book = Book::new(DEFAULT_DEPTH);
+        for s in 0..SYMMETRIES {
+            let moves: Vec<_> = GAME.iter().map(|&p| symmetry(s, p)).collect();
+            book.add_game(&moves, Outcome::BlackWon);
+        }
+
+        assert_eq!(book.len(), GAME.len());
+        for s in 0..SYMMETRIES {
+            let moves: Vec<_> = GAME.iter().map(|&p| symmetry(s, p)).collect();
+            for i in 0..GAME.len() {
+                let played = book.moves(&moves[..i]);
+                assert_eq!(played.len(), 1);
+                assert_eq!(played[0].games, 8);
+                assert_eq!(played[0].wins, if i % 2 == 0 { 8 } else { 0 });
+            }
+            assert_eq!(book.best_move(&moves[..4]), Some(moves[4]));
+        }
+        assert_eq!(book.best_move(&[]), Some((7, 7)));
+        assert_eq!(book.best_move(&GAME), None);
+    }
+
+    #[test]
+    fn books_survive_saving() {
+        let mut book = Book::new(4);
+        book.add_game(&GAME, Outcome::WhiteWon);
+        book.add_game(&GAME[..3], Outcome::Draw);
+        assert!(!book.add_game(&GAME, Outcome::NotFinished));
+
+        let mut file = vec![];
+        book.write_to(&mut file).unwrap();
+        assert_eq!(file.len(), 10 + 4 * 15);
+        assert_eq!(Book::read_from(&mut &file[..]).unwrap(), book);
+
+        file[4] = VERSION + 1;
+        assert!(Book::read_from(&mut &file[..]).is_err());
+    }
+}
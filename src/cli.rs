@@ -0,0 +1,459 @@
+use gomoku::analysis::{self, AnalysisOptions};
+use gomoku::archive;
+use gomoku::board::*;
+use gomoku::book::{self, Book};
+use gomoku::paths::Dirs;
+use gomoku::profile::Profile;
+use gomoku::render::{self, Art, RenderOptions};
+use gomoku::replay::{self, ReplayOptions};
+use gomoku::saves;
+use gomoku::shapes;
+use gomoku::svg::{self, SvgOptions};
+use gomoku::tune;
+use std::fs;
+use std::path::PathBuf;
+
+const USAGE: &str = "Usage: gomoku [--data-dir DIR] [--assets-dir DIR] [--log-dir DIR] [COMMAND]
+
+Without a command the game window is opened.
+
+Commands:
+    convert [SAVES] [ARCHIVE]    Converts a games.txt save file to a binary archive
+    check [SAVES] [--fix] [--output FILE]
+                                 Reports broken and duplicate games in a save file,
+                                 --fix rewrites it without them (keeping a .bak copy)
+    render OUTPUT.png GAME [--size PX] [--no-numbers] [--no-highlight]
+                                 Draws a game into a PNG image
+    gif OUTPUT.gif GAME [--size PX] [--delay MS] [--final-delay MS] [--no-numbers]
+                                 Writes an animated replay of a game
+    gif OUTPUT_DIR --all [--saves FILE] [...]
+                                 Writes a replay of every game in a save file
+    svg OUTPUT.svg GAME [--cell PX] [--no-numbers] [--no-coordinates] [--mark MARK]...
+                                 Writes a vector diagram of a game
+    analyze [SAVES] [--game ID] [--depth N] [--mistake X] [--blunder X] [--output FILE]
+                                 Compares every move to the best one the AI finds and lists
+                                 the mistakes and blunders, --output writes them as notes
+    book [SAVES|ARCHIVE.gma]... [--depth N] [--output FILE]
+                                 Builds the opening book of the AI from game collections
+    tune [SAVES] [--profile FILE] [--iterations N] [--name NAME] [--output FILE]
+                                 Fits the evaluation weights of a profile to the results
+                                 of the saved games and writes them as a new profile
+
+MARK is one of triangle:h8, square:h8, circle:h8, letter:h8:A or arrow:h8:k11.
+
+GAME is one of:
+    --code CODE                  A position code from \"Copy code\"
+    --moves MOVES                A move list like [[7,7],[7,8]]
+    --save ID [--saves FILE]     The ID-th game of a save file, counting from 0";
+
+/// Runs the command given on the command line instead of the game.
+pub fn run(dirs: &Dirs, args: &[String]) -> Result<(), String> {
+    let (command, args) = match args.split_first() {
+        Some((command, args)) => (command.as_str(), args),
+        None => return Ok(()),
+    };
+
+    match command {
+        "convert" => convert(dirs, args),
+        "check" => check(dirs, args),
+        "render" => render(dirs, args),
+        "svg" => svg(dirs, args),
+        "gif" => gif(dirs, args),
+        "analyze" => analyze(dirs, args),
+        "book" => book(dirs, args),
+        "tune" => tune(dirs, args),
+        "help" | "--help" | "-h" => {
+            println!("{}", USAGE);
+            Ok(())
+        }
+        _ => Err(format!("Unknown command: {}\n\n{}", command, USAGE)),
+    }
+}
+
+fn path_arg(args: &[String], id: usize, default: PathBuf) -> PathBuf {
+    args.get(id).map(PathBuf::from).unwrap_or(default)
+}
+
+fn convert(dirs: &Dirs, args: &[String]) -> Result<(), String> {
+    let saves = path_arg(args, 0, dirs.saves_path());
+    let output = path_arg(args, 1, dirs.data.join("games.gma"));
+
+    let (converted, skipped) = archive::convert_saves(&saves, &output)
+        .map_err(|e| format!("Failed to convert {}: {}", saves.display(), e))?;
+
+    println!(
+        "{} games written to {}, {} malformed entries skipped",
+        converted,
+        output.display(),
+        skipped
+    );
+    Ok(())
+}
+
+fn check(dirs: &Dirs, args: &[String]) -> Result<(), String> {
+    let (positional, options) = options(args, &["--fix"])?;
+    let path = positional
+        .first()
+        .map(PathBuf::from)
+        .unwrap_or_else(|| dirs.saves_path());
+    let games = read_saves(&path).map_err(|e| format!("{}: {}", path.display(), e))?;
+
+    let report = saves::check_saves(&games);
+    for (id, problem) in &report.problems {
+        println!("Game {}: {}", id, problem);
+    }
+    println!(
+        "{} games checked, {} problems found",
+        games.len(),
+        report.problems.len()
+    );
+
+    let output = match (option(&options, "--output"), option(&options, "--fix")) {
+        (Some(output), _) => PathBuf::from(output),
+        (None, Some(_)) if !report.problems.is_empty() => {
+            let mut backup = path.clone().into_os_string();
+            backup.push(".bak");
+            fs::copy(&path, &backup).map_err(|e| format!("Failed to back up: {}", e))?;
+            path
+        }
+        (None, Some(_)) => return Ok(()),
+        (None, None) => {
+            if !report.problems.is_empty() {
+                println!("Run with --fix to rewrite the file without them");
+            }
+            return Ok(());
+        }
+    };
+    saves::write_saves(&output, &report.repaired)
+        .map_err(|e| format!("Failed to write {}: {}", output.display(), e))?;
+    println!(
+        "{} games written to {}",
+        report.repaired.len(),
+        output.display()
+    );
+    Ok(())
+}
+
+type Options<'a> = Vec<(&'a str, &'a str)>;
+
+/// Splits `args` into positional arguments and `--flag value` options.
+fn options<'a>(
+    args: &'a [String],
+    switches: &[&str],
+) -> Result<(Vec<&'a str>, Options<'a>), String> {
+    let mut positional = vec![];
+    let mut options = vec![];
+    let mut args = args.iter();
+
+    while let Some(arg) = args.next() {
+        if switches.contains(&arg.as_str()) {
+            options.push((arg.as_str(), ""));
+        } else if arg.starts_with("--") {
+            let value = args
+                .next()
+                .ok_or_else(|| format!("Missing value after {}", arg))?;
+            options.push((arg.as_str(), value.as_str()));
+        } else {
+            positional.push(arg.as_str());
+        }
+    }
+
+    Ok((positional, options))
+}
+
+fn option<'a>(options: &[(&str, &'a str)], name: &str) -> Option<&'a str> {
+    options
+        .iter()
+        .rev()
+        .find(|(n, _)| *n == name)
+        .map(|(_, v)| *v)
+}
+
+fn option_values<'a>(options: &[(&str, &'a str)], name: &str) -> Vec<&'a str> {
+    options
+        .iter()
+        .filter(|(n, _)| *n == name)
+        .map(|(_, v)| *v)
+        .collect()
+}
+
+fn parse_option<T: std::str::FromStr>(
+    options: &[(&str, &str)],
+    name: &str,
+) -> Result<Option<T>, String> {
+    option(options, name)
+        .map(|v| {
+            v.parse()
+                .map_err(|_| format!("Invalid value for {}: {}", name, v))
+        })
+        .transpose()
+}
+
+/// The board described by the `--code`, `--moves` or `--save` option.
+fn game_arg(dirs: &Dirs, options: &[(&str, &str)]) -> Result<Board, String> {
+    if let Some(code) = option(options, "--code") {
+        return Board::from_code(code);
+    }
+    if let Some(moves) = option(options, "--moves") {
+        let mut board = Board::default();
+        board
+            .load_from_string(moves)
+            .map_err(|e| format!("Invalid move list: {}", e))?;
+        return Ok(board);
+    }
+    if let Some(id) = parse_option::<usize>(options, "--save")? {
+        let saves = option(options, "--saves")
+            .map(PathBuf::from)
+            .unwrap_or_else(|| dirs.saves_path());
+        let games = read_saves(&saves).map_err(|e| format!("{}: {}", saves.display(), e))?;
+        let (_, moves) = games
+            .get(id)
+            .ok_or_else(|| format!("{} has only {} games", saves.display(), games.len()))?;
+        let mut board = Board::default();
+        board
+            .load_from_string(moves)
+            .map_err(|e| format!("Game {} is malformed: {}", id, e))?;
+        return Ok(board);
+    }
+    Err("No game given, use --code, --moves or --save".to_owned())
+}
+
+fn render(dirs: &Dirs, args: &[String]) -> Result<(), String> {
+    let (positional, options) = options(args, &["--no-numbers", "--no-highlight"])?;
+    let output = positional
+        .first()
+        .ok_or_else(|| format!("No output file given\n\n{}", USAGE))?;
+    let board = game_arg(dirs, &options)?;
+
+    let mut render_options = RenderOptions::default();
+    if let Some(size) = parse_option(&options, "--size")? {
+        render_options.size = size;
+    }
+    render_options.move_numbers = option(&options, "--no-numbers").is_none();
+    if option(&options, "--no-highlight").is_some() {
+        render_options.highlight_last = 0;
+    }
+
+    render::render_png(&board, &dirs.images_dir(), output.as_ref(), &render_options)
+        .map_err(|e| format!("Failed to render {}: {}", output, e))
+}
+
+fn svg(dirs: &Dirs, args: &[String]) -> Result<(), String> {
+    let switches = ["--no-numbers", "--no-coordinates", "--no-winning-line"];
+    let (positional, options) = options(args, &switches)?;
+    let output = positional
+        .first()
+        .ok_or_else(|| format!("No output file given\n\n{}", USAGE))?;
+    let board = game_arg(dirs, &options)?;
+
+    let mut svg_options = SvgOptions::default();
+    if let Some(cell) = parse_option(&options, "--cell")? {
+        svg_options.cell = cell;
+    }
+    svg_options.move_numbers = option(&options, "--no-numbers").is_none();
+    svg_options.coordinates = option(&options, "--no-coordinates").is_none();
+    svg_options.winning_line = option(&options, "--no-winning-line").is_none();
+    for mark in option_values(&options, "--mark") {
+        svg_options
+            .marks
+            .push(Mark::parse(mark).ok_or_else(|| format!("Invalid mark: {}", mark))?);
+    }
+
+    fs::write(output, svg::board_to_svg(&board, &svg_options))
+        .map_err(|e| format!("Failed to write {}: {}", output, e))
+}
+
+fn gif(dirs: &Dirs, args: &[String]) -> Result<(), String> {
+    let (positional, options) = options(args, &["--no-numbers", "--all"])?;
+    let output = PathBuf::from(
+        positional
+            .first()
+            .ok_or_else(|| format!("No output given\n\n{}", USAGE))?,
+    );
+
+    let mut replay_options = ReplayOptions::default();
+    if let Some(size) = parse_option(&options, "--size")? {
+        replay_options.size = size;
+    }
+    if let Some(delay) = parse_option(&options, "--delay")? {
+        replay_options.delay = delay;
+    }
+    if let Some(delay) = parse_option(&options, "--final-delay")? {
+        replay_options.final_delay = delay;
+    }
+    replay_options.move_numbers = option(&options, "--no-numbers").is_none();
+
+    let art = Art::load(&dirs.images_dir()).map_err(|e| format!("Failed to load images: {}", e))?;
+
+    if option(&options, "--all").is_none() {
+        let board = game_arg(dirs, &options)?;
+        return replay::save_gif(&board, &art, &replay_options, &output)
+            .map_err(|e| format!("Failed to write {}: {}", output.display(), e));
+    }
+
+    let saves = option(&options, "--saves")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| dirs.saves_path());
+    let games = read_saves(&saves).map_err(|e| format!("{}: {}", saves.display(), e))?;
+    fs::create_dir_all(&output).map_err(|e| format!("{}: {}", output.display(), e))?;
+
+    for (id, (_, moves)) in games.iter().enumerate() {
+        let mut board = Board::default();
+        if board.load_from_string(moves).is_err() {
+            eprintln!("Game {} is malformed, skipped", id);
+            continue;
+        }
+        let path = output.join(format!("game_{:03}.gif", id));
+        replay::save_gif(&board, &art, &replay_options, &path)
+            .map_err(|e| format!("Failed to write {}: {}", path.display(), e))?;
+        println!("{} written", path.display());
+    }
+    Ok(())
+}
+
+fn analyze(dirs: &Dirs, args: &[String]) -> Result<(), String> {
+    warn_broken_shapes();
+    let (positional, options) = options(args, &[])?;
+    let saves = positional
+        .first()
+        .map(PathBuf::from)
+        .unwrap_or_else(|| dirs.saves_path());
+    let mut games = read_saves(&saves).map_err(|e| format!("{}: {}", saves.display(), e))?;
+
+    let mut analysis_options = AnalysisOptions::default();
+    if let Some(depth) = parse_option(&options, "--depth")? {
+        analysis_options.depth = depth;
+    }
+    if let Some(mistake) = parse_option(&options, "--mistake")? {
+        analysis_options.mistake = mistake;
+    }
+    if let Some(blunder) = parse_option(&options, "--blunder")? {
+        analysis_options.blunder = blunder;
+    }
+    let only = parse_option::<usize>(&options, "--game")?;
+    if only.map_or(false, |id| id >= games.len()) {
+        return Err(format!(
+            "{} has only {} games",
+            saves.display(),
+            games.len()
+        ));
+    }
+
+    for (id, (_, moves)) in games.iter_mut().enumerate() {
+        if only.map_or(false, |only| only != id) {
+            continue;
+        }
+        let mut board = Board::default();
+        if let Err(e) = board.load_from_string(moves) {
+            println!("Game {}: malformed, skipped ({})", id, e);
+            continue;
+        }
+        let reviews = analysis::analyze(&board, &analysis_options);
+        println!("Game {}: {}", id, analysis::summary(&reviews));
+        for review in reviews.iter().filter(|r| r.symbol.is_some()) {
+            println!(
+                "    {}. {}{} ({:.2}), {} ({:.2}) was better",
+                review.id,
+                point_name(review.played),
+                review.symbol.map(MoveSymbol::as_str).unwrap_or_default(),
+                review.value,
+                point_name(review.best),
+                review.best_value
+            );
+        }
+        analysis::annotate(&mut board, &reviews);
+        *moves = board.to_save_string();
+    }
+
+    if let Some(output) = option(&options, "--output") {
+        saves::write_saves(output.as_ref(), &games)
+            .map_err(|e| format!("Failed to write {}: {}", output, e))?;
+        println!("Annotated games written to {}", output);
+    }
+    Ok(())
+}
+
+fn book(dirs: &Dirs, args: &[String]) -> Result<(), String> {
+    let (positional, options) = options(args, &[])?;
+    let depth = parse_option(&options, "--depth")?.unwrap_or(book::DEFAULT_DEPTH);
+    let output = option(&options, "--output")
+        .map(PathBuf::from)
+        .unwrap_or_else(|| dirs.book_path());
+    let inputs: Vec<PathBuf> = if positional.is_empty() {
+        vec![dirs.saves_path()]
+    } else {
+        positional.iter().map(PathBuf::from).collect()
+    };
+
+    let mut book = Book::new(depth);
+    let mut games = 0;
+    for input in &inputs {
+        let error = |e: std::io::Error| format!("{}: {}", input.display(), e);
+        if input.extension().map_or(false, |e| e == "gma") {
+            games += book.add_archive(input).map_err(error)?;
+        } else {
+            games += book.add_saves(&read_saves(input).map_err(error)?);
+        }
+    }
+
+    book.save(&output)
+        .map_err(|e| format!("Failed to write {}: {}", output.display(), e))?;
+    println!(
+        "{} positions from {} finished games written to {}",
+        book.len(),
+        games,
+        output.display()
+    );
+    Ok(())
+}
+
+/// Tells when the shapes file of the user is broken, the AI uses the built in shapes then.
+fn warn_broken_shapes() {
+    if let Some(e) = &shapes::user_shapes().1 {
+        eprintln!("{}, using the built in shapes", e);
+    }
+}
+
+fn tune(dirs: &Dirs, args: &[String]) -> Result<(), String> {
+    warn_broken_shapes();
+    let (positional, options) = options(args, &[])?;
+    let saves = positional
+        .first()
+        .map(PathBuf::from)
+        .unwrap_or_else(|| dirs.saves_path());
+    let games = read_saves(&saves).map_err(|e| format!("{}: {}", saves.display(), e))?;
+    let base = match option(&options, "--profile") {
+        Some(path) => Profile::load(path.as_ref())?,
+        None => Profile::default(),
+    };
+    let iterations = parse_option(&options, "--iterations")?.unwrap_or(1000);
+
+    let (samples, used) = tune::samples(&games);
+    if samples.is_empty() {
+        return Err(format!(
+            "{} has no finished games to learn from",
+            saves.display()
+        ));
+    }
+    println!("{} positions from {} finished games", samples.len(), used);
+
+    let mut tuning = tune::tune(&base, &samples, iterations);
+    tuning.profile.name = option(&options, "--name").unwrap_or("Tuned").to_owned();
+    println!(
+        "Error {:.4} -> {:.4} (scale {:.3})",
+        tuning.error_before, tuning.error_after, tuning.scale
+    );
+
+    let output = match option(&options, "--output") {
+        Some(output) => PathBuf::from(output),
+        None => {
+            let dir = dirs.profiles_dir();
+            fs::create_dir_all(&dir).map_err(|e| format!("{}: {}", dir.display(), e))?;
+            dir.join("tuned.json")
+        }
+    };
+    fs::write(&output, tuning.profile.to_json())
+        .map_err(|e| format!("Failed to write {}: {}", output.display(), e))?;
+    println!("{} written", output.display());
+    Ok(())
+}
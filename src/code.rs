@@ -0,0 +1,161 @@
+//! Short, URL-safe codes describing a game, handy for sharing positions.
+//!
+//! A code is a version character, the board size and the rule set as one character each,
+//! followed by the moves in base64url, every move packed into one byte as `x << 4 | y`.
+
+use super::board::*;
+
+const VERSION: char = '1';
+const FREESTYLE: char = 'f';
+/// The characters codes are made of.
+pub const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-_";
+
+fn encode_base64(data: &[u8]) -> String {
+    let mut out = String::new();
+    for chunk in data.chunks(3) {
+        let bits = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |bits, (i, b)| bits | (*b as u32) << (16 - 8 * i));
+        for i in 0..=chunk.len() {
+            out.push(ALPHABET[(bits >> (18 - 6 * i) & 63) as usize] as char);
+        }
+    }
+    out
+}
+
+fn decode_base64(text: &str) -> Option<Vec<u8>> {
+    let mut out = vec![];
+    let digits = text
+        .bytes()
+        .map(|c| ALPHABET.iter().position(|a| *a == c).map(|d| d as u32))
+        .collect::<Option<Vec<u32>>>()?;
+
+    for chunk in digits.chunks(4) {
+        if chunk.len() == 1 {
+            return None;
+        }
+        let bits = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |bits, (i, d)| bits | d << (18 - 6 * i));
+        for i in 0..chunk.len() - 1 {
+            out.push((bits >> (16 - 8 * i)) as u8);
+        }
+    }
+    Some(out)
+}
+
+impl Board {
+    pub fn to_code(&self) -> String {
+        let moves: Vec<u8> = self
+            .moves()
+            .iter()
+            .map(|(x, y)| (x << 4 | y) as u8)
+            .collect();
+
+        format!(
+            "{}{}{}{}",
+            VERSION,
+            ALPHABET[MAP_SIZE] as char,
+            FREESTYLE,
+            encode_base64(&moves)
+        )
+    }
+    pub fn from_code(code: &str) -> Result<Board, String> {
+        let code = code.trim();
+        let mut header = code.chars();
+
+        match header.next() {
+            Some(VERSION) => {}
+            Some(v) => return Err(format!("Unknown code version {}", v)),
+            None => return Err("Empty code".to_owned()),
+        }
+        let size = match header.next() {
+            Some(c) => ALPHABET
+                .iter()
+                .position(|a| *a as char == c)
+                .ok_or_else(|| format!("Invalid board size {}", c))?,
+            None => return Err("Code is too short".to_owned()),
+        };
+        if size != MAP_SIZE {
+            return Err(format!("{0}x{0} boards are not supported", size));
+        }
+        match header.next() {
+            Some(FREESTYLE) => {}
+            Some(r) => return Err(format!("Unknown rule set {}", r)),
+            None => return Err("Code is too short".to_owned()),
+        }
+
+        let moves = decode_base64(header.as_str()).ok_or_else(|| "Malformed code".to_owned())?;
+
+        let mut board = Board::default();
+        for m in moves {
+            let (x, y) = ((m >> 4) as usize, (m & 15) as usize);
+            if x >= MAP_SIZE || y >= MAP_SIZE || board.cell(x, y) != Cell::Empty {
+                return Err(format!("Illegal move ({}, {}) in code", x, y));
+            }
+            board.put(x, y);
+        }
+        Ok(board)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn board(moves: &[(usize, usize)]) -> Board {
+        let mut board = Board::default();
+        for &(x, y) in moves {
+            board.put(x, y);
+        }
+        board
+    }
+
+    #[test]
+    fn codes_give_back_the_moves() {
+        let games: [&[(usize, usize)]; 5] = [
+            &[],
+            &[(7, 7)],
+            &[(7, 7), (7, 8)],
+            &[(7, 7), (7, 8), (8, 8)],
+            &[(0, 0), (14, 14), (0, 14), (14, 0), (3, 11), (11, 3), (7, 7)],
+        ];
+        for moves in games.iter() {
+            let code = board(moves).to_code();
+            assert!(code.bytes().all(|c| ALPHABET.contains(&c)), "{}", code);
+            assert_eq!(Board::from_code(&code).unwrap().moves(), &moves.to_vec());
+        }
+    }
+
+    #[test]
+    fn code_starts_with_the_header() {
+        assert_eq!(board(&[]).to_code(), "1Pf");
+        assert_eq!(board(&[(7, 7)]).to_code(), "1Pfdw");
+        assert_eq!(
+            Board::from_code("  1Pfdw\n").unwrap().moves(),
+            &vec![(7, 7)]
+        );
+    }
+
+    #[test]
+    fn malformed_codes_are_rejected() {
+        let error = |code| Board::from_code(code).err().unwrap();
+        assert_eq!(error(""), "Empty code");
+        assert_eq!(error("2Pf"), "Unknown code version 2");
+        assert_eq!(error("1"), "Code is too short");
+        assert_eq!(error("1!f"), "Invalid board size !");
+        assert_eq!(error("1Tf"), "19x19 boards are not supported");
+        assert_eq!(error("1P"), "Code is too short");
+        assert_eq!(error("1Pr"), "Unknown rule set r");
+        assert_eq!(error("1Pfd"), "Malformed code");
+        assert_eq!(error("1Pfd*"), "Malformed code");
+        assert_eq!(error("1Pf_w"), "Illegal move (15, 15) in code");
+        let twice = format!("1Pf{}", encode_base64(&[0x77, 0x77]));
+        assert_eq!(
+            Board::from_code(&twice).err().unwrap(),
+            "Illegal move (7, 7) in code"
+        );
+    }
+}
@@ -8,6 +8,8 @@ use super::board::GameResult;
 pub enum PlayerInt {
     Human,
     AI,
+    /// The AI choosing its moves with Monte Carlo tree search.
+    Mcts,
 }
 
 impl PlayerInt {
@@ -15,8 +17,34 @@ impl PlayerInt {
         match self {
             PlayerInt::Human => "Human".to_owned(),
             PlayerInt::AI => "AI".to_owned(),
+            PlayerInt::Mcts => "MCTS".to_owned(),
         }
     }
+    pub fn from_string(s: &str) -> Option<PlayerInt> {
+        match s {
+            "Human" => Some(PlayerInt::Human),
+            "AI" => Some(PlayerInt::AI),
+            "MCTS" => Some(PlayerInt::Mcts),
+            _ => None,
+        }
+    }
+}
+
+/// The players of black and white the main menu cycles through, in order.
+pub const PLAYER_CHOICES: [(PlayerInt, PlayerInt); 5] = [
+    (PlayerInt::Human, PlayerInt::Human),
+    (PlayerInt::AI, PlayerInt::Human),
+    (PlayerInt::Human, PlayerInt::AI),
+    (PlayerInt::Mcts, PlayerInt::Human),
+    (PlayerInt::Human, PlayerInt::Mcts),
+];
+
+/// The players after `players` on the main menu, two humans for any pair it doesn't offer.
+pub fn next_players(players: (PlayerInt, PlayerInt)) -> (PlayerInt, PlayerInt) {
+    match PLAYER_CHOICES.iter().position(|&p| p == players) {
+        Some(i) => PLAYER_CHOICES[(i + 1) % PLAYER_CHOICES.len()],
+        None => PLAYER_CHOICES[0],
+    }
 }
 
 #[derive(Debug, Clone, Eq, PartialEq)]
@@ -24,5 +52,7 @@ pub enum GameState {
     MainMenu,
     Playing,
     LoadSaved,
+    /// Typing or pasting the code of a game to open.
+    EnterCode,
     Finished(GameResult),
 }
@@ -0,0 +1,23 @@
+extern crate chrono;
+extern crate gif;
+extern crate glui;
+extern crate glui_proc;
+extern crate png;
+extern crate rand;
+extern crate serde_json;
+
+pub mod ai;
+pub mod analysis;
+pub mod archive;
+pub mod board;
+pub mod book;
+pub mod code;
+pub mod gamestate;
+pub mod paths;
+pub mod profile;
+pub mod render;
+pub mod replay;
+pub mod saves;
+pub mod shapes;
+pub mod svg;
+pub mod tune;
@@ -1,56 +1,52 @@
 #![windows_subsystem = "windows"]
 extern crate glui;
 extern crate glui_proc;
+extern crate gomoku;
 extern crate rand;
 
 use glui::mecs::*;
 use glui::tools::*;
 
-mod ai;
-mod board;
-mod gamestate;
+mod cli;
 mod ui;
-use gamestate::*;
+use gomoku::ai::{DEFAULT_LEVEL, LEVELS};
+use gomoku::board::Board;
+use gomoku::gamestate::*;
+use gomoku::paths;
+use gomoku::shapes;
 use ui::*;
 
-use std::fs::OpenOptions;
+use std::env;
+use std::fs;
+use std::fs::File;
 use std::io::Write;
 
-// use ai::ai_eval;
-// use std::fs::File;
-// use std::io;
-// use std::io::BufRead;
-
 fn main() {
-    // let mut lines: Vec<String> = Default::default();
-    //
-    // if let Ok(file) = File::open("data/games.txt") {
-    //     lines = io::BufReader::new(file)
-    //         .lines()
-    //         .map(|r| r.unwrap_or_default())
-    //         .filter(|l| l.len() > 0 && l.as_bytes()[0] == '[' as u8)
-    //         .collect();
-    // }
-    //
-    // println!("{} entries read", lines.len());
-    //
-    // let mut file = OpenOptions::new()
-    //     .write(true)
-    //     .create(true)
-    //     .open("data/evals.txt")
-    //     .unwrap();
-    // for i in 0..lines.len() {
-    //     let s = &lines[i];
-    //     let vals = ai_eval(s, true);
-    //     writeln!(file, "{}\n{:?}", s, vals.unwrap()).unwrap();
-    //     println!("Entry {} done!", i + 1);
-    // }
+    let (dirs, args) = match paths::apply_args(env::args().skip(1).collect()) {
+        Ok(parsed) => parsed,
+        Err(e) => {
+            eprintln!("{}", e);
+            return;
+        }
+    };
+    if let Err(e) = dirs.copy_legacy_saves() {
+        eprintln!("Failed to copy {}: {}", paths::LEGACY_SAVES, e);
+    }
+    let shapes_error = &shapes::load_user_shapes(&dirs.shapes_path()).1;
+    if !args.is_empty() {
+        if let Err(e) = cli::run(&dirs, &args) {
+            eprintln!("{}", e);
+            std::process::exit(1);
+        }
+        return;
+    }
 
     let mut w: World = World::new_win(Vec2::new(640.0, 480.0), "Gomoku by Matyi", Vec3::grey(0.1));
 
     let rt = w.window_info().unwrap();
 
-    if let Ok(mut file) = OpenOptions::new().create(true).open("ogl.txt") {
+    let _ = fs::create_dir_all(&dirs.log);
+    if let Ok(mut file) = File::create(dirs.gl_log_path()) {
         let _ = write!(file, "OpenGL version: {}", rt.gl_verison);
     };
 
@@ -59,6 +55,17 @@ fn main() {
         state: GameState::MainMenu,
         intelligence: (PlayerInt::Human, PlayerInt::Human),
         save_id: 0,
+        code: String::new(),
+        notice: shapes_error
+            .as_ref()
+            .map(|e| format!("{}, using the built in shapes", e)),
+        levels: [LEVELS[DEFAULT_LEVEL]; 2],
+        profiles: Default::default(),
+        engine: SharedEngine::new(&dirs),
+        worker: Default::default(),
+        resume: Board::load_resume(&dirs.resume_path()),
+        resumable: false,
+        dirs,
     });
     w.run();
 }
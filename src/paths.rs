@@ -0,0 +1,162 @@
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+pub const DATA_DIR_VAR: &str = "GOMOKU_DATA_DIR";
+pub const ASSETS_DIR_VAR: &str = "GOMOKU_ASSETS_DIR";
+pub const LOG_DIR_VAR: &str = "GOMOKU_LOG_DIR";
+
+/// Where the saves were kept before they moved to the data directory, relative to the
+/// working directory.
+pub const LEGACY_SAVES: &str = "data/games.txt";
+
+/// The directories the game reads and writes, resolved once at startup and passed to
+/// whatever needs them.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Dirs {
+    /// Where saves and the resume slot live, `$XDG_DATA_HOME/gomoku` unless overridden.
+    pub data: PathBuf,
+    /// The directory holding `images/`.
+    pub assets: PathBuf,
+    /// Where logs are written, `$XDG_STATE_HOME/gomoku` unless overridden.
+    pub log: PathBuf,
+}
+
+/// Consumes the `--data-dir`, `--assets-dir` and `--log-dir` options, returns the directories
+/// and the rest of the arguments.
+///
+/// The options take the place of the corresponding environment variables.
+pub fn apply_args(args: Vec<String>) -> Result<(Dirs, Vec<String>), String> {
+    let mut data = dir_from_var(DATA_DIR_VAR);
+    let mut assets = dir_from_var(ASSETS_DIR_VAR);
+    let mut log = dir_from_var(LOG_DIR_VAR);
+    let mut rest = vec![];
+    let mut args = args.into_iter();
+
+    while let Some(arg) = args.next() {
+        let dir = match arg.as_str() {
+            "--data-dir" => &mut data,
+            "--assets-dir" => &mut assets,
+            "--log-dir" => &mut log,
+            _ => {
+                rest.push(arg);
+                continue;
+            }
+        };
+        match args.next() {
+            Some(path) => *dir = Some(PathBuf::from(path)),
+            None => return Err(format!("Missing directory after {}", arg)),
+        }
+    }
+
+    Ok((Dirs::resolve(data, assets, log), rest))
+}
+
+fn dir_from_var(var: &str) -> Option<PathBuf> {
+    env::var_os(var)
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+}
+
+fn home_dir() -> Option<PathBuf> {
+    dir_from_var("HOME")
+}
+
+impl Dirs {
+    /// The directories given by the environment variables, or the defaults.
+    pub fn from_env() -> Dirs {
+        Dirs::resolve(
+            dir_from_var(DATA_DIR_VAR),
+            dir_from_var(ASSETS_DIR_VAR),
+            dir_from_var(LOG_DIR_VAR),
+        )
+    }
+
+    /// Fills in the defaults of the directories that are not given.
+    fn resolve(data: Option<PathBuf>, assets: Option<PathBuf>, log: Option<PathBuf>) -> Dirs {
+        let data = data
+            .or_else(|| dir_from_var("XDG_DATA_HOME").map(|dir| dir.join("gomoku")))
+            .or_else(|| home_dir().map(|dir| dir.join(".local/share/gomoku")))
+            .or_else(|| dir_from_var("APPDATA").map(|dir| dir.join("gomoku")))
+            .unwrap_or_else(|| PathBuf::from("data"));
+        let log = log
+            .or_else(|| dir_from_var("XDG_STATE_HOME").map(|dir| dir.join("gomoku")))
+            .or_else(|| home_dir().map(|dir| dir.join(".local/state/gomoku")))
+            .unwrap_or_else(|| data.clone());
+        Dirs {
+            assets: assets.unwrap_or_else(default_assets_dir),
+            data,
+            log,
+        }
+    }
+
+    pub fn saves_path(&self) -> PathBuf {
+        self.data.join("games.txt")
+    }
+
+    pub fn resume_path(&self) -> PathBuf {
+        self.data.join("resume.txt")
+    }
+
+    /// Where evaluation profiles of the user are looked for, next to the saves.
+    pub fn profiles_dir(&self) -> PathBuf {
+        self.data.join("profiles")
+    }
+
+    /// Shapes of the user, taking the place of the built in `data/shapes.txt`.
+    pub fn shapes_path(&self) -> PathBuf {
+        self.data.join("shapes.txt")
+    }
+
+    /// The opening book written by `gomoku book`.
+    pub fn book_path(&self) -> PathBuf {
+        self.data.join("book.gmb")
+    }
+
+    pub fn gl_log_path(&self) -> PathBuf {
+        self.log.join("ogl.txt")
+    }
+
+    pub fn images_dir(&self) -> PathBuf {
+        self.assets.join("images")
+    }
+
+    /// The resource name of an image in `images/`, e.g. `image("board")`.
+    pub fn image(&self, name: &str) -> String {
+        self.images_dir().join(name).to_string_lossy().into_owned()
+    }
+
+    /// Copies the saves of older versions from `data/games.txt` of the working directory, if
+    /// the data directory has none yet. Returns whether there was anything to copy.
+    pub fn copy_legacy_saves(&self) -> io::Result<bool> {
+        copy_saves_from(Path::new(LEGACY_SAVES), &self.saves_path())
+    }
+}
+
+fn copy_saves_from(legacy: &Path, saves: &Path) -> io::Result<bool> {
+    if saves.exists() || !legacy.is_file() {
+        return Ok(false);
+    }
+    if let Some(dir) = saves.parent() {
+        fs::create_dir_all(dir)?;
+    }
+    fs::copy(legacy, saves)?;
+    Ok(true)
+}
+
+/// The directory holding `images/`, looked up next to the executable.
+///
+/// A few parent directories are searched too, so `cargo run` finds the assets of the repository.
+fn default_assets_dir() -> PathBuf {
+    let exe = env::current_exe().ok();
+    let exe_dir = exe.as_ref().and_then(|exe| exe.parent());
+
+    exe_dir
+        .into_iter()
+        .flat_map(Path::ancestors)
+        .take(3)
+        .find(|dir| dir.join("images").is_dir())
+        .map(Path::to_path_buf)
+        .unwrap_or_else(|| PathBuf::from("."))
+}
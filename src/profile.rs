@@ -0,0 +1,204 @@
+//! Evaluation profiles, the playing style of the AI, stored as JSON like
+//!
+//! ```json
+//! {
+//!     "name": "Attacker",
+//!     "aggression": 0.6,
+//!     "weights": {"open_three": 1.5},
+//!     "forks": {"double_four": 20, "four_three": 10, "double_three": 2}
+//! }
+//! ```
+//!
+//! Threats left out of `weights` keep their weight in `data/shapes.txt`, forks left out are worth nothing.
+
+use super::shapes::*;
+use std::fs;
+use std::path::Path;
+
+/// Bonuses for having several threats at once, on top of the weights of the threats.
+#[derive(Debug, Default, Copy, Clone, PartialEq)]
+pub struct Forks {
+    /// Two fours, open or not.
+    pub double_four: f64,
+    /// A four and an open three.
+    pub four_three: f64,
+    /// Two open threes.
+    pub double_three: f64,
+}
+
+impl Forks {
+    const NAMES: [&'static str; 3] = ["double_four", "four_three", "double_three"];
+
+    fn get_mut(&mut self, name: &str) -> Option<&mut f64> {
+        match name {
+            "double_four" => Some(&mut self.double_four),
+            "four_three" => Some(&mut self.four_three),
+            "double_three" => Some(&mut self.double_three),
+            _ => None,
+        }
+    }
+    fn values(&self) -> [f64; 3] {
+        [self.double_four, self.four_three, self.double_three]
+    }
+    /// The bonus of a player with `counts` threats, indexed by `Threat`.
+    pub fn bonus(&self, counts: &[u8; THREATS]) -> f64 {
+        let fours = counts[Threat::OpenFour as usize] + counts[Threat::HalfOpenFour as usize];
+        let threes = counts[Threat::OpenThree as usize];
+        let mut bonus = 0.0;
+        if fours >= 2 {
+            bonus += self.double_four;
+        }
+        if fours >= 1 && threes >= 1 {
+            bonus += self.four_three;
+        }
+        if threes >= 2 {
+            bonus += self.double_three;
+        }
+        bonus
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Profile {
+    pub name: String,
+    /// Indexed by `Threat`, an open three is worth 1.
+    pub weights: [f64; THREATS],
+    /// How much the AI values its own threats against the ones of its opponent, 0.5 is even.
+    pub aggression: f32,
+    pub forks: Forks,
+}
+
+impl Profile {
+    pub fn from_json(text: &str) -> Result<Profile, String> {
+        let json: serde_json::Value = serde_json::from_str(text).map_err(|e| e.to_string())?;
+        let json = json.as_object().ok_or("a profile is a JSON object")?;
+        let number = |key: &str, value: &serde_json::Value| {
+            value
+                .as_f64()
+                .ok_or_else(|| format!("{} is not a number", key))
+        };
+
+        let mut profile = Profile {
+            name: String::new(),
+            weights: Shapes::default().weights,
+            aggression: 0.5,
+            forks: Forks::default(),
+        };
+        for (key, value) in json {
+            match key.as_str() {
+                "name" => {
+                    profile.name = value.as_str().ok_or("name is not a string")?.to_owned();
+                }
+                "aggression" => {
+                    let aggression = number(key, value)?;
+                    if !(0.0..=1.0).contains(&aggression) {
+                        return Err("aggression is not between 0 and 1".to_owned());
+                    }
+                    profile.aggression = aggression as f32;
+                }
+                "weights" => {
+                    let weights = value.as_object().ok_or("weights is not an object")?;
+                    for (name, weight) in weights {
+                        let threat = Threat::parse(name)
+                            .ok_or_else(|| format!("unknown threat {}", name))?;
+                        profile.weights[threat as usize] = number(name, weight)?;
+                    }
+                }
+                "forks" => {
+                    let forks = value.as_object().ok_or("forks is not an object")?;
+                    for (name, bonus) in forks {
+                        *profile
+                            .forks
+                            .get_mut(name)
+                            .ok_or_else(|| format!("unknown fork {}", name))? =
+                            number(name, bonus)?;
+                    }
+                }
+                _ => return Err(format!("unknown key {}", key)),
+            }
+        }
+        if profile.name.is_empty() {
+            return Err("the profile has no name".to_owned());
+        }
+        Ok(profile)
+    }
+    pub fn to_json(&self) -> String {
+        let weights: serde_json::Map<String, serde_json::Value> = Threat::ALL
+            .iter()
+            .map(|&t| (t.name().to_owned(), self.weights[t as usize].into()))
+            .collect();
+        let forks: serde_json::Map<String, serde_json::Value> = Forks::NAMES
+            .iter()
+            .zip(&self.forks.values())
+            .map(|(name, &bonus)| (name.to_string(), bonus.into()))
+            .collect();
+        let json = serde_json::json!({
+            "name": self.name,
+            "aggression": self.aggression.to_string().parse::<f64>().unwrap_or(0.5),
+            "weights": weights,
+            "forks": forks,
+        });
+        serde_json::to_string_pretty(&json).unwrap_or_default()
+    }
+    pub fn load(path: &Path) -> Result<Profile, String> {
+        let text = fs::read_to_string(path).map_err(|e| e.to_string())?;
+        Profile::from_json(&text).map_err(|e| format!("{}: {}", path.display(), e))
+    }
+}
+
+impl Default for Profile {
+    fn default() -> Profile {
+        builtin_profiles().remove(0)
+    }
+}
+
+/// The profiles in `data/profiles`, the first one is the default.
+pub fn builtin_profiles() -> Vec<Profile> {
+    [
+        include_str!("../data/profiles/balanced.json"),
+        include_str!("../data/profiles/attacker.json"),
+        include_str!("../data/profiles/defender.json"),
+    ]
+    .iter()
+    .map(|text| Profile::from_json(text).expect("a profile in data/profiles is broken"))
+    .collect()
+}
+
+/// The built in profiles followed by the `*.json` files in `dir`, along with the errors
+/// of the files that could not be read.
+pub fn load_profiles(dir: &Path) -> (Vec<Profile>, Vec<String>) {
+    let mut profiles = builtin_profiles();
+    let mut errors = vec![];
+    let mut paths: Vec<_> = fs::read_dir(dir)
+        .into_iter()
+        .flatten()
+        .filter_map(|entry| entry.ok().map(|e| e.path()))
+        .filter(|path| path.extension().map_or(false, |e| e == "json"))
+        .collect();
+    paths.sort();
+    for path in paths {
+        match Profile::load(&path) {
+            Ok(profile) => profiles.push(profile),
+            Err(e) => errors.push(e),
+        }
+    }
+    (profiles, errors)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn balanced_plays_like_the_old_engine() {
+        let profile = &builtin_profiles()[0];
+        assert_eq!(profile.name, "Balanced");
+        assert_eq!(
+            Shapes::builtin().weights,
+            [100.0, 10.0, 5.0, 1.0, 0.1, 0.01]
+        );
+        assert_eq!(profile.weights, Shapes::default().weights);
+        assert_eq!(profile.aggression, 0.51);
+        assert_eq!(profile.forks, Forks::default());
+    }
+}
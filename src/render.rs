@@ -0,0 +1,423 @@
+//! Software rendering of boards with the game's art, works without an OpenGL context.
+
+use super::board::*;
+use png::{BitDepth, ColorType, Decoder, Encoder, Transformations};
+use std::fs::File;
+use std::io;
+use std::io::{BufReader, BufWriter};
+use std::path::Path;
+
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Rect {
+    pub x: f32,
+    pub y: f32,
+    pub w: f32,
+    pub h: f32,
+}
+
+impl Rect {
+    pub fn new(x: f32, y: f32, w: f32, h: f32) -> Rect {
+        Rect { x, y, w, h }
+    }
+    /// Removes `ratio` of the size from every side, like a relative padding.
+    pub fn shrink(self, ratio: f32) -> Rect {
+        Rect {
+            x: self.x + self.w * ratio,
+            y: self.y + self.h * ratio,
+            w: self.w * (1.0 - 2.0 * ratio),
+            h: self.h * (1.0 - 2.0 * ratio),
+        }
+    }
+}
+
+/// An RGBA image with straight alpha, channels in the `0..1` range.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Image {
+    pub width: usize,
+    pub height: usize,
+    pub pixels: Vec<[f32; 4]>,
+}
+
+impl Image {
+    pub fn new(width: usize, height: usize, color: [f32; 4]) -> Image {
+        Image {
+            width,
+            height,
+            pixels: vec![color; width * height],
+        }
+    }
+    pub fn load_png(path: &Path) -> io::Result<Image> {
+        let mut decoder = Decoder::new(BufReader::new(File::open(path)?));
+        decoder
+            .set_transformations(Transformations::normalize_to_color8() | Transformations::ALPHA);
+        let mut reader = decoder
+            .read_info()
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+
+        let mut buf = vec![0; reader.output_buffer_size()];
+        let info = reader
+            .next_frame(&mut buf)
+            .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))?;
+
+        let channels = match info.color_type {
+            ColorType::Rgba => 4,
+            _ => 2,
+        };
+        let pixels = buf[..info.buffer_size()]
+            .chunks(channels)
+            .map(|p| {
+                let c = |i: usize| p[i] as f32 / 255.0;
+                if channels == 4 {
+                    [c(0), c(1), c(2), c(3)]
+                } else {
+                    [c(0), c(0), c(0), c(1)]
+                }
+            })
+            .collect();
+
+        Ok(Image {
+            width: info.width as usize,
+            height: info.height as usize,
+            pixels,
+        })
+    }
+    pub fn to_rgba8(&self) -> Vec<u8> {
+        self.pixels
+            .iter()
+            .flat_map(|p| p.iter().map(|c| (c.clamp(0.0, 1.0) * 255.0).round() as u8))
+            .collect()
+    }
+    pub fn save_png(&self, path: &Path) -> io::Result<()> {
+        let file = BufWriter::new(File::create(path)?);
+        let mut encoder = Encoder::new(file, self.width as u32, self.height as u32);
+        encoder.set_color(ColorType::Rgba);
+        encoder.set_depth(BitDepth::Eight);
+
+        encoder
+            .write_header()
+            .and_then(|mut writer| writer.write_image_data(&self.to_rgba8()))
+            .map_err(|e| io::Error::new(io::ErrorKind::Other, e))
+    }
+    fn blend(&mut self, x: usize, y: usize, color: [f32; 4]) {
+        let dst = &mut self.pixels[y * self.width + x];
+        let a = color[3] + dst[3] * (1.0 - color[3]);
+        if a > 0.0 {
+            for i in 0..3 {
+                dst[i] = (color[i] * color[3] + dst[i] * dst[3] * (1.0 - color[3])) / a;
+            }
+        }
+        dst[3] = a;
+    }
+    fn pixel_range(&self, rect: Rect) -> (usize, usize, usize, usize) {
+        let clamp = |v: f32, max: usize| v.clamp(0.0, max as f32) as usize;
+        (
+            clamp(rect.x.round(), self.width),
+            clamp(rect.y.round(), self.height),
+            clamp((rect.x + rect.w).round(), self.width),
+            clamp((rect.y + rect.h).round(), self.height),
+        )
+    }
+    pub fn fill_rect(&mut self, rect: Rect, color: [f32; 4]) {
+        let (x0, y0, x1, y1) = self.pixel_range(rect);
+        for y in y0..y1 {
+            for x in x0..x1 {
+                self.blend(x, y, color);
+            }
+        }
+    }
+    /// Draws `img` stretched over `rect`, averaging source pixels when scaling down.
+    pub fn draw_image(&mut self, img: &Image, rect: Rect, alpha: f32) {
+        let (x0, y0, x1, y1) = self.pixel_range(rect);
+        let scale_x = img.width as f32 / rect.w;
+        let scale_y = img.height as f32 / rect.h;
+        let samples = (scale_x.max(scale_y).ceil() as usize).clamp(1, 8);
+
+        for y in y0..y1 {
+            for x in x0..x1 {
+                let mut sum = [0.0; 4];
+                for sy in 0..samples {
+                    for sx in 0..samples {
+                        let sub_x = (sx as f32 + 0.5) / samples as f32;
+                        let sub_y = (sy as f32 + 0.5) / samples as f32;
+                        let u = (x as f32 + sub_x - rect.x) * scale_x;
+                        let v = (y as f32 + sub_y - rect.y) * scale_y;
+                        if u < 0.0 || v < 0.0 || u >= img.width as f32 || v >= img.height as f32 {
+                            continue;
+                        }
+                        let p = img.pixels[v as usize * img.width + u as usize];
+                        for i in 0..3 {
+                            sum[i] += p[i] * p[3];
+                        }
+                        sum[3] += p[3];
+                    }
+                }
+                if sum[3] > 0.0 {
+                    let n = (samples * samples) as f32;
+                    let color = [
+                        sum[0] / sum[3],
+                        sum[1] / sum[3],
+                        sum[2] / sum[3],
+                        sum[3] / n * alpha,
+                    ];
+                    self.blend(x, y, color);
+                }
+            }
+        }
+    }
+    /// Draws `n` with a small built-in digit font, centered on (`cx`, `cy`).
+    pub fn draw_number(&mut self, n: usize, cx: f32, cy: f32, height: f32, color: [f32; 4]) {
+        let digits = n.to_string();
+        let px = height / 5.0;
+        let width = digits.len() as f32 * 4.0 * px - px;
+        let left = cx - width / 2.0;
+        let top = cy - height / 2.0;
+
+        for (i, d) in digits.bytes().enumerate() {
+            let glyph = DIGITS[(d - b'0') as usize];
+            for row in 0..5 {
+                for col in 0..3 {
+                    if glyph >> (14 - row * 3 - col) & 1 == 1 {
+                        let x = left + (i as f32 * 4.0 + col as f32) * px;
+                        let y = top + row as f32 * px;
+                        self.fill_rect(Rect::new(x, y, px, px), color);
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// 3x5 pixel digits, rows from top to bottom, 3 bits each.
+const DIGITS: [u16; 10] = [
+    0b111_101_101_101_111,
+    0b010_110_010_010_111,
+    0b111_001_111_100_111,
+    0b111_001_111_001_111,
+    0b101_101_111_001_001,
+    0b111_100_111_001_111,
+    0b111_100_111_101_111,
+    0b111_001_001_001_001,
+    0b111_101_111_101_111,
+    0b111_101_111_001_111,
+];
+
+/// The images from `images/` that make up a board.
+#[derive(Debug, Clone)]
+pub struct Art {
+    pub board: Image,
+    pub board_shadow: Image,
+    pub stone_shadow: Image,
+    pub black: Image,
+    pub white: Image,
+    pub black_highlighted: Image,
+    pub white_highlighted: Image,
+}
+
+impl Art {
+    pub fn load(images: &Path) -> io::Result<Art> {
+        let load = |name: &str| {
+            let path = images.join(name).with_extension("png");
+            Image::load_png(&path)
+                .map_err(|e| io::Error::new(e.kind(), format!("{}: {}", path.display(), e)))
+        };
+        Ok(Art {
+            board: load("board")?,
+            board_shadow: load("board_shadow")?,
+            stone_shadow: load("stone_shadow")?,
+            black: load("black")?,
+            white: load("white")?,
+            black_highlighted: load("black_highlighted")?,
+            white_highlighted: load("white_highlighted")?,
+        })
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RenderOptions {
+    pub size: usize,
+    pub move_numbers: bool,
+    /// How many of the latest moves are highlighted while the game is on, the game shows two.
+    pub highlight_last: usize,
+    pub background: [f32; 4],
+}
+
+impl Default for RenderOptions {
+    fn default() -> Self {
+        RenderOptions {
+            size: 640,
+            move_numbers: true,
+            highlight_last: 2,
+            background: [0.45, 0.3, 0.18, 1.0],
+        }
+    }
+}
+
+/// The stones drawn highlighted: the winning five once the game is over, the latest moves before.
+pub fn highlighted_stones(board: &Board, highlight_last: usize) -> Vec<(usize, usize)> {
+    match board.result() {
+        GameResult::BlackWon(pts) | GameResult::WhiteWon(pts) => pts,
+        GameResult::Draw => vec![],
+        GameResult::NotFinished => board
+            .moves()
+            .iter()
+            .rev()
+            .take(highlight_last)
+            .cloned()
+            .collect(),
+    }
+}
+
+/// The area of the intersection (`n`, `k`), laid out like `ui::board_gui` does.
+pub fn cell_rect(options: &RenderOptions, n: usize, k: usize) -> Rect {
+    let grid = board_rect(options).shrink(1.0 / 32.0);
+    let cell = grid.w / MAP_SIZE as f32;
+    Rect::new(
+        grid.x + k as f32 * cell,
+        grid.y + n as f32 * cell,
+        cell,
+        cell,
+    )
+}
+
+fn board_rect(options: &RenderOptions) -> Rect {
+    let size = options.size as f32;
+    Rect::new(0.0, 0.0, size, size).shrink(0.04).shrink(0.05)
+}
+
+pub fn render_board(board: &Board, art: &Art, options: &RenderOptions) -> Image {
+    let size = options.size as f32;
+    let mut img = Image::new(options.size, options.size, options.background);
+
+    let area = Rect::new(0.0, 0.0, size, size).shrink(0.04);
+    let shadow = Rect::new(
+        area.x + area.w * 0.05,
+        area.y + area.h * 0.05,
+        area.w * 0.95,
+        area.h * 0.95,
+    );
+    img.draw_image(&art.board_shadow, shadow, 1.0);
+    img.draw_image(&art.board, board_rect(options), 1.0);
+
+    let highlighted = highlighted_stones(board, options.highlight_last);
+    let ids = board.move_to_id_map();
+
+    for n in 0..MAP_SIZE {
+        for k in 0..MAP_SIZE {
+            let cell = cell_rect(options, n, k).shrink(0.03);
+            let heat = board.heat[n][k];
+            if heat > 0.0 {
+                img.fill_rect(cell, [1.0, 0.0, 0.0, heat]);
+            }
+
+            let (stone, text_color) = match (board.cell(n, k), highlighted.contains(&(n, k))) {
+                (Cell::Empty, _) => continue,
+                (Cell::Black, false) => (&art.black, [1.0; 4]),
+                (Cell::Black, true) => (&art.black_highlighted, [1.0; 4]),
+                (Cell::White, false) => (&art.white, [0.0, 0.0, 0.0, 1.0]),
+                (Cell::White, true) => (&art.white_highlighted, [0.0, 0.0, 0.0, 1.0]),
+            };
+
+            let shadow = Rect::new(
+                cell.x + cell.w * 0.03,
+                cell.y + cell.h * 0.03,
+                cell.w * 1.1,
+                cell.h * 1.1,
+            );
+            img.draw_image(&art.stone_shadow, shadow, 1.0);
+            img.draw_image(stone, cell.shrink(0.03), 1.0);
+
+            if options.move_numbers && !highlighted.contains(&(n, k)) {
+                let id = ids[&(n, k)];
+                img.draw_number(
+                    id,
+                    cell.x + cell.w / 2.0,
+                    cell.y + cell.h / 2.0,
+                    cell.h * 0.35,
+                    text_color,
+                );
+            }
+        }
+    }
+
+    img
+}
+
+/// Renders `board` with the images in `images_dir` into a PNG file.
+pub fn render_png(
+    board: &Board,
+    images_dir: &Path,
+    path: &Path,
+    options: &RenderOptions,
+) -> io::Result<()> {
+    let art = Art::load(images_dir)?;
+    render_board(board, &art, options).save_png(path)
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use std::fs;
+
+    /// Art of single colored images, so the tests do not need the image files.
+    pub(crate) fn flat_art() -> Art {
+        let image = |color| Image::new(4, 4, color);
+        Art {
+            board: image([0.8, 0.6, 0.3, 1.0]),
+            board_shadow: image([0.0, 0.0, 0.0, 0.5]),
+            stone_shadow: image([0.0, 0.0, 0.0, 0.3]),
+            black: image([0.0, 0.0, 0.0, 1.0]),
+            white: image([1.0, 1.0, 1.0, 1.0]),
+            black_highlighted: image([0.2, 0.2, 0.2, 1.0]),
+            white_highlighted: image([0.9, 0.9, 0.9, 1.0]),
+        }
+    }
+
+    fn center(img: &Image, rect: Rect) -> [f32; 4] {
+        let (x, y) = (rect.x + rect.w / 2.0, rect.y + rect.h / 2.0);
+        img.pixels[y as usize * img.width + x as usize]
+    }
+
+    #[test]
+    fn png_has_the_requested_size() {
+        let mut board = Board::default();
+        board.put(7, 7);
+        let options = RenderOptions {
+            size: 200,
+            ..Default::default()
+        };
+        let path = std::env::temp_dir().join(format!("gomoku-render-{}.png", std::process::id()));
+        let img = render_board(&board, &flat_art(), &options);
+        let saved = img.save_png(&path).and_then(|_| Image::load_png(&path));
+        let _ = fs::remove_file(&path);
+
+        assert_eq!((img.width, img.height), (200, 200));
+        let saved = saved.unwrap();
+        assert_eq!((saved.width, saved.height), (200, 200));
+        assert_eq!(saved.to_rgba8(), img.to_rgba8());
+    }
+
+    #[test]
+    fn stones_are_drawn_on_their_intersections() {
+        let mut board = Board::default();
+        board.put(7, 7);
+        board.put(3, 11);
+        let options = RenderOptions {
+            move_numbers: false,
+            highlight_last: 0,
+            ..Default::default()
+        };
+        let img = render_board(&board, &flat_art(), &options);
+
+        let empty = center(&img, cell_rect(&options, 0, 0));
+        assert_eq!(center(&img, cell_rect(&options, 7, 8)), empty);
+        assert_eq!(
+            center(&img, cell_rect(&options, 7, 7)),
+            [0.0, 0.0, 0.0, 1.0]
+        );
+        assert_eq!(
+            center(&img, cell_rect(&options, 3, 11)),
+            [1.0, 1.0, 1.0, 1.0]
+        );
+        assert_ne!(empty, [0.0, 0.0, 0.0, 1.0]);
+    }
+}
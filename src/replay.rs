@@ -0,0 +1,119 @@
+//! Animated GIF replays of games, drawn with the software renderer.
+
+use super::board::*;
+use super::render::{render_board, Art, RenderOptions};
+use gif::{Encoder, Frame, Repeat};
+use std::fs::File;
+use std::io;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReplayOptions {
+    pub size: usize,
+    /// Time a move stays on screen, in milliseconds.
+    pub delay: u32,
+    /// Time the final position stays on screen before the replay restarts, in milliseconds.
+    pub final_delay: u32,
+    pub move_numbers: bool,
+}
+
+impl Default for ReplayOptions {
+    fn default() -> Self {
+        ReplayOptions {
+            size: 400,
+            delay: 700,
+            final_delay: 3000,
+            move_numbers: true,
+        }
+    }
+}
+
+fn encoding_error(e: gif::EncodingError) -> io::Error {
+    io::Error::new(io::ErrorKind::Other, e)
+}
+
+/// Writes the empty board, then one frame for every move of `board` with the latest move
+/// highlighted, the final frame shows the winning five if there is one.
+pub fn write_gif<W: Write>(
+    board: &Board,
+    art: &Art,
+    options: &ReplayOptions,
+    out: W,
+) -> io::Result<()> {
+    let size = options.size.min(u16::max_value() as usize);
+    let render_options = RenderOptions {
+        size,
+        move_numbers: options.move_numbers,
+        highlight_last: 1,
+        ..Default::default()
+    };
+
+    let mut encoder = Encoder::new(out, size as u16, size as u16, &[]).map_err(encoding_error)?;
+    encoder
+        .set_repeat(Repeat::Infinite)
+        .map_err(encoding_error)?;
+
+    let moves = board.moves();
+    let frames = moves.len() + 1;
+    let mut replay = Board::default();
+    for i in 0..frames {
+        if let Some(&(x, y)) = i.checked_sub(1).and_then(|m| moves.get(m)) {
+            replay.put(x, y);
+        }
+
+        let mut pixels = render_board(&replay, art, &render_options).to_rgba8();
+        let mut frame = Frame::from_rgba_speed(size as u16, size as u16, &mut pixels, 10);
+        let delay = if i + 1 == frames {
+            options.final_delay
+        } else {
+            options.delay
+        };
+        frame.delay = (delay / 10).min(u16::max_value() as u32) as u16;
+        encoder.write_frame(&frame).map_err(encoding_error)?;
+    }
+
+    encoder.into_inner()?.flush()
+}
+
+pub fn save_gif(board: &Board, art: &Art, options: &ReplayOptions, path: &Path) -> io::Result<()> {
+    write_gif(board, art, options, BufWriter::new(File::create(path)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::render::tests::flat_art;
+
+    /// The delays of the frames of a GIF, in hundredths of a second.
+    fn frame_delays(gif: &[u8]) -> Vec<u16> {
+        let mut decoder = gif::DecodeOptions::new().read_info(gif).unwrap();
+        let mut delays = vec![];
+        while let Some(frame) = decoder.read_next_frame().unwrap() {
+            assert_eq!((frame.width, frame.height), (60, 60));
+            delays.push(frame.delay);
+        }
+        delays
+    }
+
+    #[test]
+    fn replay_shows_the_empty_board_and_every_move() {
+        let options = ReplayOptions {
+            size: 60,
+            delay: 500,
+            final_delay: 2000,
+            move_numbers: false,
+        };
+        let mut board = Board::default();
+        for &(x, y) in &[(7, 7), (7, 8), (8, 8)] {
+            board.put(x, y);
+        }
+        let mut gif = vec![];
+        write_gif(&board, &flat_art(), &options, &mut gif).unwrap();
+        assert_eq!(frame_delays(&gif), vec![50, 50, 50, 200]);
+
+        let mut gif = vec![];
+        write_gif(&Board::default(), &flat_art(), &options, &mut gif).unwrap();
+        assert_eq!(frame_delays(&gif), vec![200]);
+    }
+}
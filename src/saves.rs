@@ -0,0 +1,318 @@
+//! Checking and repairing save files like `games.txt`.
+
+use super::board::*;
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::path::Path;
+
+type Moves = Vec<(usize, usize)>;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Problem {
+    /// The move list can not be read at all, the game is dropped when repairing.
+    Malformed(String),
+    /// The `id`-th move is off the board, the game is cut before it.
+    OffBoard { id: usize, pos: (i64, i64) },
+    /// The `id`-th move is on a taken intersection, the game is cut before it.
+    PlayedTwice { id: usize, pos: (usize, usize) },
+    /// A five was made by the `id`-th move but the game goes on, the rest is cut.
+    PlayedAfterFive { id: usize },
+    /// The same game as an earlier one, maybe mirrored or rotated, it is dropped.
+    Duplicate { of: usize, symmetric: bool },
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Problem::Malformed(e) => write!(f, "malformed move list: {}", e),
+            Problem::OffBoard { id, pos } => {
+                write!(f, "move {} ({}, {}) is off the board", id, pos.0, pos.1)
+            }
+            Problem::PlayedTwice { id, pos } => {
+                write!(f, "move {} ({}, {}) is played twice", id, pos.0, pos.1)
+            }
+            Problem::PlayedAfterFive { id } => {
+                write!(f, "the game goes on after move {} made five", id)
+            }
+            Problem::Duplicate { of, symmetric } => write!(
+                f,
+                "duplicate of game {}{}",
+                of,
+                if *symmetric {
+                    " (mirrored or rotated)"
+                } else {
+                    ""
+                }
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Report {
+    /// The problems found, along with the index of the game they were found in.
+    pub problems: Vec<(usize, Problem)>,
+    /// The games with every problem repaired, in the format of `read_saves`.
+    pub repaired: Vec<(String, String)>,
+}
+
+/// The same moves for every symmetric copy of a game, the smallest one.
+fn canonical(moves: &[(usize, usize)]) -> Moves {
+    (0..SYMMETRIES)
+        .map(|s| moves.iter().map(|&p| symmetry(s, p)).collect::<Vec<_>>())
+        .min()
+        .unwrap_or_default()
+}
+
+/// Reads the moves of a saved game and keeps the part that can be played.
+fn check_moves(json: &serde_json::Value) -> Result<(Moves, Vec<Problem>), Problem> {
+    let moves = if json.is_object() {
+        &json["moves"]
+    } else {
+        json
+    };
+    let moves: Vec<(i64, i64)> =
+        serde_json::from_value(moves.clone()).map_err(|e| Problem::Malformed(e.to_string()))?;
+
+    let mut board = Board::default();
+    let mut problems = vec![];
+    for (i, &(x, y)) in moves.iter().enumerate() {
+        let id = i + 1;
+        if x < 0 || y < 0 || x >= MAP_SIZE as i64 || y >= MAP_SIZE as i64 {
+            problems.push(Problem::OffBoard { id, pos: (x, y) });
+            break;
+        }
+        let pos = (x as usize, y as usize);
+        if board.cell(pos.0, pos.1) != Cell::Empty {
+            problems.push(Problem::PlayedTwice { id, pos });
+            break;
+        }
+        match board.put(pos.0, pos.1) {
+            GameResult::BlackWon(_) | GameResult::WhiteWon(_) if id < moves.len() => {
+                problems.push(Problem::PlayedAfterFive { id });
+                break;
+            }
+            _ => {}
+        }
+    }
+
+    Ok((board.moves().clone(), problems))
+}
+
+/// Writes `moves` back in the place of the original move list, keeping the notes that still apply.
+fn repaired_line(json: &serde_json::Value, moves: &[(usize, usize)]) -> String {
+    if !json.is_object() {
+        return serde_json::to_string(moves).unwrap_or_default();
+    }
+
+    let mut json = json.clone();
+    json["moves"] = serde_json::json!(moves);
+    if let Some(notes) = json["notes"].as_object_mut() {
+        notes.retain(|id, _| id.parse::<usize>().map_or(false, |id| id <= moves.len()));
+    }
+    json.to_string()
+}
+
+/// Looks for broken and repeated games among the header and move list pairs of a save file.
+pub fn check_saves(games: &[(String, String)]) -> Report {
+    let mut report = Report::default();
+    let mut seen: HashMap<Moves, (usize, Moves)> = HashMap::new();
+
+    for (id, (header, line)) in games.iter().enumerate() {
+        let checked = serde_json::from_str::<serde_json::Value>(line)
+            .map_err(|e| Problem::Malformed(e.to_string()))
+            .and_then(|json| check_moves(&json).map(|checked| (json, checked)));
+        let (json, (moves, problems)) = match checked {
+            Ok(checked) => checked,
+            Err(problem) => {
+                report.problems.push((id, problem));
+                continue;
+            }
+        };
+        let changed = !problems.is_empty();
+        report
+            .problems
+            .extend(problems.into_iter().map(|p| (id, p)));
+
+        if let Some((of, original)) = seen.get(&canonical(&moves)) {
+            let symmetric = *original != moves;
+            report
+                .problems
+                .push((id, Problem::Duplicate { of: *of, symmetric }));
+            continue;
+        }
+        seen.insert(canonical(&moves), (id, moves.clone()));
+
+        let line = if changed {
+            repaired_line(&json, &moves)
+        } else {
+            line.clone()
+        };
+        report.repaired.push((header.clone(), line));
+    }
+
+    report
+}
+
+/// Replaces the save file at `path` with `games`.
+pub fn write_saves(path: &Path, games: &[(String, String)]) -> io::Result<()> {
+    let mut data = String::new();
+    for (header, moves) in games {
+        data.push_str(header);
+        data.push('\n');
+        data.push_str(moves);
+        data.push('\n');
+    }
+    write_atomic(path, data.as_bytes())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn a_dropped_line_breaks_one_game() {
+        // The move list of the second game is missing.
+        let file = "2020-05-09 22:18:28 \n[[7,7],[6,8]]\n\
+                    2020-05-10 20:41:21 \n\
+                    2020-05-11 15:11:57 \n[[7,7],[5,5],[6,8]]\n\
+                    2020-05-12 10:00:00 \n{\"moves\":[[1,1]]}\n";
+        let games = parse_saves(file);
+        assert_eq!(games.len(), 4);
+        assert_eq!(games[2].0, "2020-05-11 15:11:57 ");
+
+        let report = check_saves(&games);
+        assert_eq!(report.problems.len(), 1);
+        assert_eq!(report.problems[0].0, 1);
+        assert!(match report.problems[0].1 {
+            Problem::Malformed(_) => true,
+            _ => false,
+        });
+        let headers: Vec<&str> = report.repaired.iter().map(|(h, _)| h.trim()).collect();
+        assert_eq!(
+            headers,
+            vec![
+                "2020-05-09 22:18:28",
+                "2020-05-11 15:11:57",
+                "2020-05-12 10:00:00"
+            ]
+        );
+    }
+
+    /// The problems of `lines` checked as one save file, and the repaired move lists.
+    fn check(lines: &[&str]) -> (Vec<(usize, Problem)>, Vec<String>) {
+        let games: Vec<_> = lines
+            .iter()
+            .enumerate()
+            .map(|(i, line)| (format!("game {}", i), line.to_string()))
+            .collect();
+        let report = check_saves(&games);
+        let repaired = report.repaired.into_iter().map(|(_, line)| line).collect();
+        (report.problems, repaired)
+    }
+
+    #[test]
+    fn games_are_cut_before_a_move_off_the_board() {
+        let (problems, repaired) = check(&["[[7,7],[15,3],[1,1]]", "[[8,8],[3,-1]]"]);
+        assert_eq!(
+            problems,
+            vec![
+                (
+                    0,
+                    Problem::OffBoard {
+                        id: 2,
+                        pos: (15, 3)
+                    }
+                ),
+                (
+                    1,
+                    Problem::OffBoard {
+                        id: 2,
+                        pos: (3, -1)
+                    }
+                ),
+            ]
+        );
+        assert_eq!(repaired, vec!["[[7,7]]", "[[8,8]]"]);
+    }
+
+    #[test]
+    fn games_are_cut_before_a_move_played_twice() {
+        let (problems, repaired) = check(&["[[7,7],[8,8],[7,7],[1,1]]"]);
+        assert_eq!(
+            problems,
+            vec![(0, Problem::PlayedTwice { id: 3, pos: (7, 7) })]
+        );
+        assert_eq!(repaired, vec!["[[7,7],[8,8]]"]);
+    }
+
+    #[test]
+    fn games_end_with_the_five() {
+        let five = "[[7,3],[8,3],[7,4],[8,4],[7,5],[8,5],[7,6],[8,6],[7,7]]";
+        let (problems, repaired) = check(&[
+            five,
+            "[[7,3],[8,3],[7,4],[8,4],[7,5],[8,5],[7,6],[9,6],[7,7],[0,0]]",
+        ]);
+        assert_eq!(problems, vec![(1, Problem::PlayedAfterFive { id: 9 })]);
+        assert_eq!(
+            repaired,
+            vec![
+                five,
+                "[[7,3],[8,3],[7,4],[8,4],[7,5],[8,5],[7,6],[9,6],[7,7]]"
+            ]
+        );
+    }
+
+    #[test]
+    fn repeated_games_are_dropped() {
+        let (problems, repaired) = check(&[
+            "[[7,7],[6,8],[5,9]]",
+            "[[7,7],[6,8],[5,9]]",
+            "[[7,7],[8,6],[9,5]]",
+            "{\"moves\":[[7,7],[6,8],[5,9]]}",
+            "[[7,7],[6,8]]",
+        ]);
+        assert_eq!(
+            problems,
+            vec![
+                (
+                    1,
+                    Problem::Duplicate {
+                        of: 0,
+                        symmetric: false
+                    }
+                ),
+                (
+                    2,
+                    Problem::Duplicate {
+                        of: 0,
+                        symmetric: true
+                    }
+                ),
+                (
+                    3,
+                    Problem::Duplicate {
+                        of: 0,
+                        symmetric: false
+                    }
+                ),
+            ]
+        );
+        assert_eq!(repaired, vec!["[[7,7],[6,8],[5,9]]", "[[7,7],[6,8]]"]);
+    }
+
+    #[test]
+    fn notes_of_cut_moves_are_dropped() {
+        let line = r#"{"moves":[[7,7],[8,8],[7,7]],"notes":{"1":"center","2":"block","3":"oops"},"players":["Human","AI"]}"#;
+        let (problems, repaired) = check(&[line]);
+        assert_eq!(problems.len(), 1);
+        let json: serde_json::Value = serde_json::from_str(&repaired[0]).unwrap();
+        assert_eq!(json["moves"], serde_json::json!([[7, 7], [8, 8]]));
+        assert_eq!(
+            json["notes"],
+            serde_json::json!({"1": "center", "2": "block"})
+        );
+        assert_eq!(json["players"], serde_json::json!(["Human", "AI"]));
+    }
+}
@@ -0,0 +1,347 @@
+//! The shapes the AI counts on every line of the board, written as strings.
+//!
+//! In a pattern `X` is a stone of the player, `O` a stone of the opponent, `_` an empty
+//! intersection, `#` the edge of the board and `-` anything but a stone of the player,
+//! so `"_XXXX_"` is an open four. Every line of `data/shapes.txt` names a threat,
+//! gives its weight and lists the patterns counted as one:
+//!
+//! ```text
+//! open_four 10: _XXXX_
+//! ```
+//!
+//! A `shapes.txt` in the data directory takes the place of the built in file when the program starts.
+
+use std::fmt;
+use std::fs;
+use std::path::Path;
+use std::sync::OnceLock;
+
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Threat {
+    Five,
+    OpenFour,
+    HalfOpenFour,
+    OpenThree,
+    HalfOpenThree,
+    OpenTwo,
+}
+
+pub const THREATS: usize = 6;
+
+impl Threat {
+    pub const ALL: [Threat; THREATS] = [
+        Threat::Five,
+        Threat::OpenFour,
+        Threat::HalfOpenFour,
+        Threat::OpenThree,
+        Threat::HalfOpenThree,
+        Threat::OpenTwo,
+    ];
+    pub fn name(self) -> &'static str {
+        match self {
+            Threat::Five => "five",
+            Threat::OpenFour => "open_four",
+            Threat::HalfOpenFour => "half_open_four",
+            Threat::OpenThree => "open_three",
+            Threat::HalfOpenThree => "half_open_three",
+            Threat::OpenTwo => "open_two",
+        }
+    }
+    pub fn parse(name: &str) -> Option<Threat> {
+        Threat::ALL.iter().cloned().find(|t| t.name() == name)
+    }
+}
+
+/// One intersection of a line, or of a pattern.
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Square {
+    Mine,
+    Theirs,
+    Empty,
+    Edge,
+    /// Only in patterns, anything but `Mine`.
+    NotMine,
+}
+
+impl Square {
+    pub fn parse(c: char) -> Option<Square> {
+        match c {
+            'X' => Some(Square::Mine),
+            'O' => Some(Square::Theirs),
+            '_' => Some(Square::Empty),
+            '#' => Some(Square::Edge),
+            '-' => Some(Square::NotMine),
+            _ => None,
+        }
+    }
+    pub fn symbol(self) -> char {
+        match self {
+            Square::Mine => 'X',
+            Square::Theirs => 'O',
+            Square::Empty => '_',
+            Square::Edge => '#',
+            Square::NotMine => '-',
+        }
+    }
+    /// Whether the pattern square `self` accepts `square` of a line.
+    fn fits(self, square: Square) -> bool {
+        match self {
+            Square::NotMine => square != Square::Mine,
+            _ => self == square,
+        }
+    }
+}
+
+/// The longest pattern, the evaluator looks at this many intersections at once.
+pub const MAX_LEN: usize = 8;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Shape {
+    pub squares: Vec<Square>,
+    pub threat: Threat,
+}
+
+impl Shape {
+    pub fn parse(pattern: &str, threat: Threat) -> Result<Shape, String> {
+        let squares = pattern
+            .chars()
+            .map(|c| {
+                Square::parse(c).ok_or_else(|| format!("unknown square '{}' in {}", c, pattern))
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        if !squares.contains(&Square::Mine) {
+            Err(format!("{} has no stone of the player", pattern))
+        } else if squares.len() > MAX_LEN {
+            Err(format!("{} is longer than {} squares", pattern, MAX_LEN))
+        } else {
+            Ok(Shape { squares, threat })
+        }
+    }
+    /// Whether the shape starts at the first of `line`, anything past its end is the edge.
+    pub fn matches(&self, line: &[Square]) -> bool {
+        self.squares
+            .iter()
+            .enumerate()
+            .all(|(i, s)| s.fits(line.get(i).cloned().unwrap_or(Square::Edge)))
+    }
+}
+
+impl fmt::Display for Shape {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for s in &self.squares {
+            write!(f, "{}", s.symbol())?;
+        }
+        Ok(())
+    }
+}
+
+/// The shapes counted by the evaluator, and what each threat is worth.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Shapes {
+    pub shapes: Vec<Shape>,
+    /// Indexed by `Threat`, an open three is worth 1.
+    pub weights: [f64; THREATS],
+}
+
+impl Shapes {
+    /// Reads shapes in the format of `data/shapes.txt`, lines starting with `//` are skipped.
+    pub fn parse(text: &str) -> Result<Shapes, String> {
+        let mut shapes = Shapes {
+            shapes: vec![],
+            weights: [0.0; THREATS],
+        };
+        for (i, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with("//") {
+                continue;
+            }
+            let error = |e: String| format!("line {}: {}", i + 1, e);
+            let colon = line
+                .find(':')
+                .ok_or_else(|| error("expected `threat weight: patterns`".to_owned()))?;
+            let mut head = line[..colon].split_whitespace();
+            let name = head.next().unwrap_or_default();
+            let threat =
+                Threat::parse(name).ok_or_else(|| error(format!("unknown threat {}", name)))?;
+            let weight = head
+                .next()
+                .and_then(|w| w.parse().ok())
+                .ok_or_else(|| error(format!("{} has no weight", name)))?;
+            shapes.set_weight(threat, weight);
+            for pattern in line[colon + 1..].split_whitespace() {
+                shapes.add(pattern, threat).map_err(error)?;
+            }
+        }
+        Ok(shapes)
+    }
+    /// The shapes of `data/shapes.txt` as built into the program.
+    pub fn builtin() -> Shapes {
+        Shapes::parse(include_str!("../data/shapes.txt")).expect("data/shapes.txt is broken")
+    }
+    pub fn load(path: &Path) -> Result<Shapes, String> {
+        fs::read_to_string(path)
+            .map_err(|e| e.to_string())
+            .and_then(|text| Shapes::parse(&text))
+            .map_err(|e| format!("{}: {}", path.display(), e))
+    }
+    pub fn add(&mut self, pattern: &str, threat: Threat) -> Result<(), String> {
+        self.shapes.push(Shape::parse(pattern, threat)?);
+        Ok(())
+    }
+    pub fn weight(&self, threat: Threat) -> f64 {
+        self.weights[threat as usize]
+    }
+    pub fn set_weight(&mut self, threat: Threat, weight: f64) {
+        self.weights[threat as usize] = weight;
+    }
+    /// The threats on a whole line written like a pattern, sorted.
+    pub fn threats(&self, line: &str) -> Result<Vec<Threat>, String> {
+        let mut squares = vec![Square::Edge];
+        for c in line.chars() {
+            match Square::parse(c) {
+                Some(Square::NotMine) | None => return Err(format!("unknown square '{}'", c)),
+                Some(s) => squares.push(s),
+            }
+        }
+        squares.push(Square::Edge);
+
+        let mut threats = vec![];
+        for start in 0..squares.len() {
+            for shape in &self.shapes {
+                if shape.matches(&squares[start..]) {
+                    threats.push(shape.threat);
+                }
+            }
+        }
+        threats.sort();
+        Ok(threats)
+    }
+}
+
+static USER_SHAPES: OnceLock<(Shapes, Option<String>)> = OnceLock::new();
+
+/// Reads the `shapes.txt` of the user at `path` for `user_shapes`, keeping the built in shapes
+/// if there is none. If it can not be read, the built in shapes along with the error. Only the
+/// first call does anything, so it belongs at startup.
+pub fn load_user_shapes(path: &Path) -> &'static (Shapes, Option<String>) {
+    USER_SHAPES.get_or_init(|| {
+        if !path.exists() {
+            return (Shapes::builtin(), None);
+        }
+        match Shapes::load(path) {
+            Ok(shapes) => (shapes, None),
+            Err(e) => (Shapes::builtin(), Some(e)),
+        }
+    })
+}
+
+/// The shapes read by `load_user_shapes`, or the built in shapes if it was not called.
+pub fn user_shapes() -> &'static (Shapes, Option<String>) {
+    USER_SHAPES.get_or_init(|| (Shapes::builtin(), None))
+}
+
+/// The shapes of the user, see `user_shapes`.
+impl Default for Shapes {
+    fn default() -> Shapes {
+        user_shapes().0.clone()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_threats(lines: &[&str], threat: Option<Threat>) {
+        let shapes = Shapes::builtin();
+        for line in lines {
+            let expected: Vec<Threat> = threat.into_iter().collect();
+            assert_eq!(shapes.threats(line).unwrap(), expected, "{}", line);
+        }
+    }
+
+    #[test]
+    fn five() {
+        assert_threats(&["XXXXX", "OXXXXXO", "_XXXXX_"], Some(Threat::Five));
+    }
+
+    #[test]
+    fn open_four() {
+        assert_threats(&["_XXXX_", "O_XXXX_O"], Some(Threat::OpenFour));
+    }
+
+    #[test]
+    fn half_open_four() {
+        assert_threats(
+            &["OXXXX_", "_XXXXO", "XXXX_", "_XXXX", "OXXXX__"],
+            Some(Threat::HalfOpenFour),
+        );
+    }
+
+    #[test]
+    fn broken_four() {
+        assert_threats(
+            &[
+                "OXXX_XO", "OXX_XXO", "OX_XXXO", "_XX_XX_", "XXX_X", "_X_XXX_",
+            ],
+            Some(Threat::HalfOpenFour),
+        );
+    }
+
+    #[test]
+    fn open_three() {
+        assert_threats(
+            &["__XXX__", "_XXX_", "O_XXX_O", "_XX_X_", "_X_XX_"],
+            Some(Threat::OpenThree),
+        );
+    }
+
+    #[test]
+    fn half_open_three() {
+        assert_threats(
+            &[
+                "OXXX__", "__XXXO", "XXX__", "__XXX", "OXX_X_", "_X_XXO", "OX_XX_", "_XX_XO",
+            ],
+            Some(Threat::HalfOpenThree),
+        );
+    }
+
+    #[test]
+    fn broken_three_at_edge() {
+        assert_threats(
+            &["XX_X_", "_X_XX", "X_XX_", "_XX_X"],
+            Some(Threat::HalfOpenThree),
+        );
+    }
+
+    #[test]
+    fn open_two() {
+        assert_threats(&["__XX__", "_XX_", "O_XX_O"], Some(Threat::OpenTwo));
+    }
+
+    #[test]
+    fn dead_shapes() {
+        assert_threats(&["OXXXXO", "OXXX_O", "OXX_XO", "X", "OX_X_O"], None);
+    }
+
+    #[test]
+    fn opponent_stones_are_not_counted() {
+        assert_threats(&["OOOOO", "_OOOO_", "_OOO_"], None);
+    }
+
+    #[test]
+    fn bad_patterns() {
+        assert!(Shape::parse("_XXA_", Threat::OpenThree).is_err());
+        assert!(Shape::parse("____", Threat::OpenTwo).is_err());
+        assert!(Shape::parse("_X_X_X_X_", Threat::OpenTwo).is_err());
+        assert!(Shapes::parse("open_four: _XXXX_").is_err());
+        assert!(Shapes::parse("double_four 10: XXXX").is_err());
+    }
+
+    #[test]
+    fn added_shapes_are_counted() {
+        let mut shapes = Shapes::builtin();
+        assert_eq!(shapes.threats("_X_X_").unwrap(), vec![]);
+        shapes.add("-_X_X_-", Threat::OpenTwo).unwrap();
+        assert_eq!(shapes.threats("_X_X_").unwrap(), vec![Threat::OpenTwo]);
+    }
+}
@@ -0,0 +1,333 @@
+//! Vector diagrams of boards for documents.
+
+use super::board::*;
+use std::fmt::Write;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct SvgOptions {
+    /// Distance of neighbouring intersections in pixels.
+    pub cell: f32,
+    pub coordinates: bool,
+    pub move_numbers: bool,
+    pub winning_line: bool,
+    pub marks: Vec<Mark>,
+}
+
+impl Default for SvgOptions {
+    fn default() -> Self {
+        SvgOptions {
+            cell: 32.0,
+            coordinates: true,
+            move_numbers: true,
+            winning_line: true,
+            marks: vec![],
+        }
+    }
+}
+
+const BACKGROUND: &str = "#dcb35c";
+const MARK_COLOR: &str = "#c8102e";
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+struct Layout {
+    cell: f32,
+    pad: f32,
+}
+
+impl Layout {
+    fn pos(&self, p: (usize, usize)) -> (f32, f32) {
+        (
+            self.pad + p.1 as f32 * self.cell,
+            self.pad + p.0 as f32 * self.cell,
+        )
+    }
+    fn size(&self) -> f32 {
+        2.0 * self.pad + (MAP_SIZE - 1) as f32 * self.cell
+    }
+}
+
+/// The color that stands out on the intersection `p`.
+fn contrast(board: &Board, p: (usize, usize)) -> &'static str {
+    match board.cell(p.0, p.1) {
+        Cell::Black => "#fff",
+        Cell::White => "#000",
+        Cell::Empty => MARK_COLOR,
+    }
+}
+
+pub fn board_to_svg(board: &Board, options: &SvgOptions) -> String {
+    let c = options.cell;
+    let layout = Layout {
+        cell: c,
+        pad: if options.coordinates {
+            c * 1.5
+        } else {
+            c * 0.75
+        },
+    };
+    let size = layout.size();
+    let mut svg = String::new();
+
+    let _ = writeln!(
+        svg,
+        r#"<svg xmlns="http://www.w3.org/2000/svg" width="{0}" height="{0}" viewBox="0 0 {0} {0}" font-family="sans-serif" text-anchor="middle">"#,
+        size
+    );
+    let _ = writeln!(
+        svg,
+        r#"<defs><marker id="arrowhead" viewBox="0 0 10 10" refX="8" refY="5" markerWidth="4" markerHeight="4" orient="auto"><path d="M0,0 L10,5 L0,10 z" fill="{}"/></marker></defs>"#,
+        MARK_COLOR
+    );
+    let _ = writeln!(
+        svg,
+        r#"<rect width="{0}" height="{0}" fill="{1}"/>"#,
+        size, BACKGROUND
+    );
+
+    let (first, last) = (layout.pad, layout.pad + (MAP_SIZE - 1) as f32 * c);
+    for i in 0..MAP_SIZE {
+        let at = layout.pad + i as f32 * c;
+        let _ = writeln!(
+            svg,
+            r#"<line x1="{0}" y1="{1}" x2="{0}" y2="{2}" stroke="black" stroke-width="1"/><line x1="{1}" y1="{0}" x2="{2}" y2="{0}" stroke="black" stroke-width="1"/>"#,
+            at, first, last
+        );
+    }
+    let mid = MAP_SIZE / 2;
+    for &p in &[
+        (3, 3),
+        (3, MAP_SIZE - 4),
+        (mid, mid),
+        (MAP_SIZE - 4, 3),
+        (MAP_SIZE - 4, MAP_SIZE - 4),
+    ] {
+        let (x, y) = layout.pos(p);
+        let _ = writeln!(svg, r#"<circle cx="{}" cy="{}" r="{}"/>"#, x, y, c * 0.1);
+    }
+
+    if options.coordinates {
+        let font = c * 0.4;
+        for i in 0..MAP_SIZE {
+            let at = layout.pad + i as f32 * c;
+            let name = point_name((i, i));
+            let (col, row) = name.split_at(1);
+            let _ = writeln!(
+                svg,
+                r#"<text x="{0}" y="{1}" font-size="{3}">{4}</text><text x="{0}" y="{2}" font-size="{3}">{4}</text>"#,
+                at,
+                layout.pad - c * 0.8,
+                size - layout.pad + c * 0.8 + font * 0.7,
+                font,
+                col.to_uppercase()
+            );
+            let _ = writeln!(
+                svg,
+                r#"<text x="{0}" y="{2}" font-size="{3}">{4}</text><text x="{1}" y="{2}" font-size="{3}">{4}</text>"#,
+                layout.pad - c * 0.9,
+                size - layout.pad + c * 0.9,
+                at + font * 0.35,
+                font,
+                row
+            );
+        }
+    }
+
+    let ids = board.move_to_id_map();
+    for n in 0..MAP_SIZE {
+        for k in 0..MAP_SIZE {
+            let (x, y) = layout.pos((n, k));
+            let (fill, stroke) = match board.cell(n, k) {
+                Cell::Empty => continue,
+                Cell::Black => ("#111", "#000"),
+                Cell::White => ("#f8f8f8", "#333"),
+            };
+            let _ = writeln!(
+                svg,
+                r#"<circle cx="{}" cy="{}" r="{}" fill="{}" stroke="{}" stroke-width="1"/>"#,
+                x,
+                y,
+                c * 0.46,
+                fill,
+                stroke
+            );
+            if options.move_numbers {
+                let font = c * 0.42;
+                let _ = writeln!(
+                    svg,
+                    r#"<text x="{}" y="{}" font-size="{}" fill="{}">{}</text>"#,
+                    x,
+                    y + font * 0.35,
+                    font,
+                    contrast(board, (n, k)),
+                    ids[&(n, k)]
+                );
+            }
+        }
+    }
+
+    if options.winning_line {
+        if let GameResult::BlackWon(pts) | GameResult::WhiteWon(pts) = board.result() {
+            let (x1, y1) = layout.pos(pts[0]);
+            let (x2, y2) = layout.pos(pts[pts.len() - 1]);
+            let _ = writeln!(
+                svg,
+                r#"<line x1="{}" y1="{}" x2="{}" y2="{}" stroke="{}" stroke-width="{}" stroke-linecap="round" opacity="0.8"/>"#,
+                x1,
+                y1,
+                x2,
+                y2,
+                MARK_COLOR,
+                c * 0.15
+            );
+        }
+    }
+
+    for mark in &options.marks {
+        write_mark(&mut svg, board, &layout, mark);
+    }
+
+    svg.push_str("</svg>\n");
+    svg
+}
+
+fn write_mark(svg: &mut String, board: &Board, layout: &Layout, mark: &Mark) {
+    let c = layout.cell;
+    let _ = match mark {
+        Mark::Triangle(p) => {
+            let (x, y) = layout.pos(*p);
+            let r = c * 0.3;
+            writeln!(
+                svg,
+                r#"<polygon points="{},{} {},{} {},{}" fill="none" stroke="{}" stroke-width="2"/>"#,
+                x,
+                y - r,
+                x - r * 0.87,
+                y + r * 0.5,
+                x + r * 0.87,
+                y + r * 0.5,
+                contrast(board, *p)
+            )
+        }
+        Mark::Square(p) => {
+            let (x, y) = layout.pos(*p);
+            let r = c * 0.22;
+            writeln!(
+                svg,
+                r#"<rect x="{}" y="{}" width="{2}" height="{2}" fill="none" stroke="{3}" stroke-width="2"/>"#,
+                x - r,
+                y - r,
+                2.0 * r,
+                contrast(board, *p)
+            )
+        }
+        Mark::Circle(p) => {
+            let (x, y) = layout.pos(*p);
+            writeln!(
+                svg,
+                r#"<circle cx="{}" cy="{}" r="{}" fill="none" stroke="{}" stroke-width="2"/>"#,
+                x,
+                y,
+                c * 0.25,
+                contrast(board, *p)
+            )
+        }
+        Mark::Letter(p, text) => {
+            let (x, y) = layout.pos(*p);
+            let font = c * 0.5;
+            if board.cell(p.0, p.1) == Cell::Empty {
+                let _ = writeln!(
+                    svg,
+                    r#"<circle cx="{}" cy="{}" r="{}" fill="{}"/>"#,
+                    x,
+                    y,
+                    c * 0.35,
+                    BACKGROUND
+                );
+            }
+            writeln!(
+                svg,
+                r#"<text x="{}" y="{}" font-size="{}" font-weight="bold" fill="{}">{}</text>"#,
+                x,
+                y + font * 0.35,
+                font,
+                contrast(board, *p),
+                escape(text)
+            )
+        }
+        Mark::Arrow(from, to) => {
+            let (x1, y1) = layout.pos(*from);
+            let (x2, y2) = layout.pos(*to);
+            writeln!(
+                svg,
+                r#"<line x1="{}" y1="{}" x2="{}" y2="{}" stroke="{}" stroke-width="{}" marker-end="url(#arrowhead)"/>"#,
+                x1,
+                y1,
+                x2,
+                y2,
+                MARK_COLOR,
+                c * 0.08
+            )
+        }
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STAR_POINTS: usize = 5;
+
+    /// Black wins on row 7 from column 3 to 7, white has four stones below.
+    fn won_board() -> Board {
+        let mut board = Board::default();
+        for i in 0..4 {
+            board.put(7, 3 + i);
+            board.put(8, 3 + i);
+        }
+        board.put(7, 7);
+        board
+    }
+
+    fn winning_lines(svg: &str) -> usize {
+        svg.matches(r#"stroke-linecap="round""#).count()
+    }
+
+    #[test]
+    fn every_stone_is_a_circle() {
+        let svg = board_to_svg(&won_board(), &SvgOptions::default());
+        assert!(svg.starts_with("<svg"));
+        assert!(svg.ends_with("</svg>\n"));
+        assert_eq!(svg.matches("<circle").count(), STAR_POINTS + 9);
+        assert_eq!(svg.matches(r##"fill="#111""##).count(), 5);
+        assert_eq!(svg.matches(r##"fill="#f8f8f8""##).count(), 4);
+    }
+
+    #[test]
+    fn winning_five_is_crossed_out() {
+        let svg = board_to_svg(&won_board(), &SvgOptions::default());
+        assert_eq!(winning_lines(&svg), 1);
+        // The padding is 1.5 cells with the coordinates on, so column 3 is at 48 + 3 * 32.
+        assert!(
+            svg.contains(r#"<line x1="144" y1="272" x2="272" y2="272""#)
+                || svg.contains(r#"<line x1="272" y1="272" x2="144" y2="272""#)
+        );
+
+        let mut board = won_board();
+        board.undo();
+        assert_eq!(
+            winning_lines(&board_to_svg(&board, &SvgOptions::default())),
+            0
+        );
+        let options = SvgOptions {
+            winning_line: false,
+            ..Default::default()
+        };
+        assert_eq!(winning_lines(&board_to_svg(&won_board(), &options)), 0);
+    }
+}
@@ -0,0 +1,273 @@
+//! Fitting the weights of a profile to the outcomes of saved games, the way Texel tuning does it:
+//! the static evaluation of a position, squashed by a sigmoid, should predict the result of the game
+//! for the player to move. The weights are moved to minimize the mean squared error of that prediction.
+
+use super::ai::Engine;
+use super::board::*;
+use super::profile::*;
+use super::shapes::*;
+
+/// The positions before this many moves say little about the result and are left out.
+const OPENING: usize = 4;
+
+/// The threat weights after `Threat::Five`, then the three fork bonuses.
+const PARAMS: usize = THREATS - 1 + 3;
+
+/// A position of a finished game.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Sample {
+    /// The threats of the player to move, indexed by `Threat`.
+    pub mine: [u8; THREATS],
+    /// The threats of the opponent.
+    pub theirs: [u8; THREATS],
+    /// 1 if the player to move went on to win, 0 if they lost, 0.5 for a draw.
+    pub result: f64,
+}
+
+/// The positions of the finished games in `games`, as returned by `read_saves`, along with
+/// the number of games they come from. Unfinished and malformed games are skipped, and so are
+/// positions with a five on the board since their result needs no evaluation.
+pub fn samples(games: &[(String, String)]) -> (Vec<Sample>, usize) {
+    let mut engine = Engine::new(&Board::default());
+    let mut samples = vec![];
+    let mut used = 0;
+
+    for (_, moves) in games {
+        let mut board = Board::default();
+        if board.load_from_string(moves).is_err() {
+            continue;
+        }
+        let black = match board.result() {
+            GameResult::BlackWon(_) => 1.0,
+            GameResult::WhiteWon(_) => 0.0,
+            GameResult::Draw => 0.5,
+            GameResult::NotFinished => continue,
+        };
+        engine.sync(&Board::default());
+        used += 1;
+
+        for (i, &pos) in board.moves().iter().enumerate() {
+            let (mine, theirs) = engine.threats();
+            let five = Threat::Five as usize;
+            if mine[five] > 0 || theirs[five] > 0 {
+                break;
+            }
+            if i >= OPENING {
+                let result = if i % 2 == 0 { black } else { 1.0 - black };
+                samples.push(Sample {
+                    mine,
+                    theirs,
+                    result,
+                });
+            }
+            if engine.apply(pos).is_err() {
+                break;
+            }
+        }
+    }
+    (samples, used)
+}
+
+/// The outcome of `tune`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Tuning {
+    pub profile: Profile,
+    /// The scale of the sigmoid turning evaluations into winning chances.
+    pub scale: f64,
+    /// The mean squared error of the base profile.
+    pub error_before: f64,
+    /// The mean squared error of the tuned profile.
+    pub error_after: f64,
+}
+
+fn sigmoid(x: f64) -> f64 {
+    1.0 / (1.0 + (-x).exp())
+}
+
+fn params(profile: &Profile) -> [f64; PARAMS] {
+    let mut params = [0.0; PARAMS];
+    params[..THREATS - 1].copy_from_slice(&profile.weights[1..]);
+    params[THREATS - 1] = profile.forks.double_four;
+    params[THREATS] = profile.forks.four_three;
+    params[THREATS + 1] = profile.forks.double_three;
+    params
+}
+
+fn set_params(profile: &mut Profile, params: &[f64; PARAMS]) {
+    profile.weights[1..].copy_from_slice(&params[..THREATS - 1]);
+    profile.forks.double_four = params[THREATS - 1];
+    profile.forks.four_three = params[THREATS];
+    profile.forks.double_three = params[THREATS + 1];
+}
+
+/// What each parameter counts in a sample, so that the evaluation is their dot product with the
+/// parameters, weighed by aggression like at the leaves of the search.
+fn features(sample: &Sample, aggression: f64) -> [f64; PARAMS] {
+    let forks = |counts: &[u8; THREATS]| {
+        let fork = |forks: Forks| forks.bonus(counts);
+        [
+            fork(Forks {
+                double_four: 1.0,
+                ..Forks::default()
+            }),
+            fork(Forks {
+                four_three: 1.0,
+                ..Forks::default()
+            }),
+            fork(Forks {
+                double_three: 1.0,
+                ..Forks::default()
+            }),
+        ]
+    };
+    let (my_forks, their_forks) = (forks(&sample.mine), forks(&sample.theirs));
+
+    let mut features = [0.0; PARAMS];
+    for (i, feature) in features.iter_mut().enumerate() {
+        let (mine, theirs) = if i < THREATS - 1 {
+            (sample.mine[i + 1] as f64, sample.theirs[i + 1] as f64)
+        } else {
+            (my_forks[i + 1 - THREATS], their_forks[i + 1 - THREATS])
+        };
+        *feature = aggression * mine - (1.0 - aggression) * theirs;
+    }
+    features
+}
+
+fn dot(a: &[f64; PARAMS], b: &[f64; PARAMS]) -> f64 {
+    a.iter().zip(b).map(|(a, b)| a * b).sum()
+}
+
+fn error(
+    params: &[f64; PARAMS],
+    features: &[[f64; PARAMS]],
+    samples: &[Sample],
+    scale: f64,
+) -> f64 {
+    let sum: f64 = features
+        .iter()
+        .zip(samples)
+        .map(|(f, s)| (s.result - sigmoid(scale * dot(params, f))).powi(2))
+        .sum();
+    sum / samples.len().max(1) as f64
+}
+
+/// The scale minimizing the error of `params`, searched for on a logarithmic scale.
+fn best_scale(params: &[f64; PARAMS], features: &[[f64; PARAMS]], samples: &[Sample]) -> f64 {
+    let error = |log: f64| error(params, features, samples, log.exp());
+    let (mut low, mut high) = ((1e-3f64).ln(), (1e2f64).ln());
+    for _ in 0..100 {
+        let a = low + (high - low) / 3.0;
+        let b = high - (high - low) / 3.0;
+        if error(a) < error(b) {
+            high = b;
+        } else {
+            low = a;
+        }
+    }
+    ((low + high) / 2.0).exp()
+}
+
+/// Tunes the threat weights and fork bonuses of `base` to `samples` with `iterations` steps of
+/// gradient descent. The weights are tuned on a logarithmic scale so that they stay positive and
+/// small ones move as readily as big ones. The weight of a five is kept, it never shows up in the samples,
+/// and nothing gets to be worth more than a five.
+pub fn tune(base: &Profile, samples: &[Sample], iterations: usize) -> Tuning {
+    const RATE: f64 = 0.05;
+    const BETA1: f64 = 0.9;
+    const BETA2: f64 = 0.999;
+    const SMALLEST: f64 = 1e-3;
+
+    let aggression = base.aggression as f64;
+    let features: Vec<_> = samples.iter().map(|s| features(s, aggression)).collect();
+    let start = params(base);
+    let scale = best_scale(&start, &features, samples);
+    let error_before = error(&start, &features, samples, scale);
+
+    let mut logs = [0.0; PARAMS];
+    for (log, weight) in logs.iter_mut().zip(&start) {
+        *log = weight.max(SMALLEST).ln();
+    }
+    let largest = base.weights[Threat::Five as usize].max(SMALLEST).ln();
+    let mut params = start;
+    let (mut m, mut v) = ([0.0; PARAMS], [0.0; PARAMS]);
+
+    for step in 1..=iterations {
+        let mut gradient = [0.0; PARAMS];
+        for (f, s) in features.iter().zip(samples) {
+            let p = sigmoid(scale * dot(&params, f));
+            let d = -2.0 * (s.result - p) * p * (1.0 - p) * scale;
+            for i in 0..PARAMS {
+                gradient[i] += d * f[i] * params[i];
+            }
+        }
+        for i in 0..PARAMS {
+            let g = gradient[i] / samples.len().max(1) as f64;
+            m[i] = BETA1 * m[i] + (1.0 - BETA1) * g;
+            v[i] = BETA2 * v[i] + (1.0 - BETA2) * g * g;
+            let m_hat = m[i] / (1.0 - BETA1.powi(step as i32));
+            let v_hat = v[i] / (1.0 - BETA2.powi(step as i32));
+            logs[i] = (logs[i] - RATE * m_hat / (v_hat.sqrt() + 1e-12)).min(largest);
+            params[i] = logs[i].exp();
+        }
+    }
+
+    let mut profile = base.clone();
+    set_params(&mut profile, &params);
+    Tuning {
+        error_after: error(&params, &features, samples, scale),
+        profile,
+        scale,
+        error_before,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sample(mine: [u8; THREATS], theirs: [u8; THREATS], result: f64) -> Sample {
+        Sample {
+            mine,
+            theirs,
+            result,
+        }
+    }
+
+    #[test]
+    fn tuning_lowers_the_error() {
+        // Open threes win here, half open fours do not.
+        let samples = vec![
+            sample([0, 0, 0, 1, 0, 0], [0, 0, 1, 0, 0, 0], 1.0),
+            sample([0, 0, 1, 0, 0, 0], [0, 0, 0, 1, 0, 0], 0.0),
+            sample([0, 0, 0, 1, 0, 2], [0, 0, 1, 0, 0, 1], 1.0),
+            sample([0, 0, 1, 0, 0, 1], [0, 0, 0, 1, 0, 2], 0.0),
+        ];
+        let base = Profile::default();
+        let tuning = tune(&base, &samples, 200);
+
+        assert!(tuning.error_after < tuning.error_before);
+        let weight = |profile: &Profile, threat: Threat| profile.weights[threat as usize];
+        assert!(weight(&tuning.profile, Threat::OpenThree) > weight(&base, Threat::OpenThree));
+        assert!(
+            weight(&tuning.profile, Threat::HalfOpenFour) < weight(&base, Threat::HalfOpenFour)
+        );
+        assert_eq!(
+            weight(&tuning.profile, Threat::Five),
+            weight(&base, Threat::Five)
+        );
+    }
+
+    #[test]
+    fn samples_follow_the_player_to_move() {
+        // Black wins with a five on the eighth row.
+        let moves = "[[7,3],[8,3],[7,4],[8,4],[7,5],[8,5],[7,6],[9,9],[7,7]]";
+        let games = vec![(String::new(), moves.to_owned())];
+        let (samples, used) = samples(&games);
+
+        assert_eq!(used, 1);
+        assert_eq!(samples.len(), 5);
+        let results: Vec<f64> = samples.iter().map(|s| s.result).collect();
+        assert_eq!(results, vec![1.0, 0.0, 1.0, 0.0, 1.0]);
+    }
+}
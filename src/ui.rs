@@ -1,3 +1,4 @@
+extern crate clipboard;
 extern crate glui;
 extern crate glui_proc;
 extern crate rand;
@@ -5,16 +6,33 @@ extern crate rand;
 use std::ops::Neg;
 use std::ops::Shl;
 
-use super::ai::*;
-use super::board::*;
-use super::gamestate::{GameState, PlayerInt};
+use clipboard::{ClipboardContext, ClipboardProvider};
 use glui::gui::*;
 use glui::impl_widget_building_for;
 use glui::mecs::*;
 use glui::tools::*;
-use std::fs::File;
+use gomoku::ai::*;
+use gomoku::analysis::{analyze_with, annotate, summary, AnalysisOptions, MoveReview};
+use gomoku::board::*;
+use gomoku::book::{Book, DEFAULT_DEPTH};
+use gomoku::code::ALPHABET;
+use gomoku::gamestate::{next_players, GameState, PlayerInt};
+use gomoku::paths::Dirs;
+use gomoku::profile::{load_profiles, Profile};
+use gomoku::render::Art;
+use gomoku::replay::{save_gif, ReplayOptions};
+use std::cell::{RefCell, RefMut};
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
 use std::io;
-use std::io::BufRead;
+use std::rc::Rc;
+use std::sync::mpsc;
+use std::sync::Arc;
+use std::thread;
+
+/// How deep the moves are searched for "hint", deeper takes seconds in busy positions.
+const HINT_DEPTH: u32 = 3;
 
 #[derive(Clone, Debug, PartialEq)]
 pub struct GameData {
@@ -22,10 +40,230 @@ pub struct GameData {
     pub state: GameState,
     pub intelligence: (PlayerInt, PlayerInt),
     pub save_id: usize,
+    /// The code typed on the "Load from code" screen.
+    pub code: String,
+    pub notice: Option<String>,
+    /// The levels of the black and the white AI.
+    pub levels: [Level; 2],
+    /// The playing styles of the black and the white AI.
+    pub profiles: [Profile; 2],
+    pub engine: SharedEngine,
+    /// Finds hints and analyses games without stopping the window.
+    pub worker: SharedWorker,
+    /// The game in the resume slot, read once at startup and kept up to date by `autosave`.
+    pub resume: Option<(Board, (PlayerInt, PlayerInt))>,
+    /// Whether the game on the board goes to the resume slot. Games started or continued do,
+    /// finished games opened to look at do not until a move is played in them.
+    pub resumable: bool,
+    /// Where the saves, the resume slot and the images are.
+    pub dirs: Dirs,
+}
+
+/// The AI of the game, shared by every copy of the `GameData`.
+#[derive(Clone)]
+pub struct SharedEngine {
+    engine: Rc<RefCell<Engine>>,
+    /// The opening book while it is read or learnt on its own thread.
+    book: Rc<RefCell<Option<mpsc::Receiver<Option<Book>>>>>,
+}
+
+impl SharedEngine {
+    pub fn new(dirs: &Dirs) -> SharedEngine {
+        let (sender, book) = mpsc::channel();
+        let dirs = dirs.clone();
+        thread::spawn(move || sender.send(opening_book(&dirs)));
+        SharedEngine {
+            engine: Rc::new(RefCell::new(Engine::new(&Board::default()))),
+            book: Rc::new(RefCell::new(Some(book))),
+        }
+    }
+
+    /// The engine, with the opening book in it once the book is ready.
+    fn get(&self) -> RefMut<Engine> {
+        let mut engine = self.engine.borrow_mut();
+        let mut pending = self.book.borrow_mut();
+        if let Some(book) = pending.take() {
+            match book.try_recv() {
+                Ok(book) => engine.set_book(book.map(Arc::new)),
+                Err(mpsc::TryRecvError::Empty) => *pending = Some(book),
+                Err(mpsc::TryRecvError::Disconnected) => {}
+            }
+        }
+        engine
+    }
+}
+
+/// The opening book in the data directory. If there is none it is learnt from the saved games
+/// and written there, so that it is only learnt once. Takes a while, so it runs on its own thread
+/// and the AI plays without a book until it is done.
+fn opening_book(dirs: &Dirs) -> Option<Book> {
+    let path = dirs.book_path();
+    match Book::load(&path) {
+        Ok(book) => Some(book),
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => {
+            let mut book = Book::new(DEFAULT_DEPTH);
+            book.add_saves(&read_saves(&dirs.saves_path()).ok()?);
+            if let Err(e) = book.save(&path) {
+                eprintln!("Failed to save {}: {}", path.display(), e);
+            }
+            Some(book)
+        }
+        Err(e) => {
+            eprintln!("Failed to load {}: {}", path.display(), e);
+            None
+        }
+    }
+}
+
+impl fmt::Debug for SharedEngine {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "SharedEngine({:?})", self.engine.borrow().moves())
+    }
+}
+
+impl PartialEq for SharedEngine {
+    fn eq(&self, other: &SharedEngine) -> bool {
+        Rc::ptr_eq(&self.engine, &other.engine)
+    }
+}
+
+/// A search done on the worker thread.
+enum Job {
+    /// The heat of the position for the player to move, in a style.
+    Hint(Board, Profile),
+    Analyze(Board),
+}
+
+/// A finished job, with the moves it was about.
+enum Done {
+    Hint(Vec<(usize, usize)>, [[f32; MAP_SIZE]; MAP_SIZE]),
+    Analysis(Vec<(usize, usize)>, Vec<MoveReview>),
+}
+
+/// Runs the jobs one after the other with the same engine, so its transposition table
+/// is only allocated once.
+fn work(jobs: mpsc::Receiver<Job>, done: mpsc::Sender<Done>) {
+    let mut engine = Engine::new(&Board::default());
+    for job in jobs {
+        let finished = match job {
+            Job::Hint(board, profile) => {
+                engine.set_profile(&profile);
+                engine.sync(&board);
+                let heat = heat_map(&engine.move_scores(HINT_DEPTH));
+                Done::Hint(board.moves().to_vec(), heat)
+            }
+            Job::Analyze(board) => {
+                engine.set_profile(&Profile::default());
+                let reviews = analyze_with(&mut engine, &board, &AnalysisOptions::default());
+                Done::Analysis(board.moves().to_vec(), reviews)
+            }
+        };
+        if done.send(finished).is_err() {
+            break;
+        }
+    }
+}
+
+struct Worker {
+    jobs: mpsc::Sender<Job>,
+    done: mpsc::Receiver<Done>,
+    /// What the jobs not finished yet are doing, the oldest first.
+    running: Vec<&'static str>,
+    hint: Option<(Vec<(usize, usize)>, [[f32; MAP_SIZE]; MAP_SIZE])>,
+    analysis: Option<(Vec<(usize, usize)>, Vec<MoveReview>)>,
+    /// Whether a job finished since the GUI was last built.
+    fresh: bool,
+}
+
+/// The worker thread of the game, shared by every copy of the `GameData`.
+#[derive(Clone)]
+pub struct SharedWorker(Rc<RefCell<Worker>>);
+
+impl Default for SharedWorker {
+    fn default() -> SharedWorker {
+        let (jobs, queue) = mpsc::channel();
+        let (finished, done) = mpsc::channel();
+        thread::spawn(move || work(queue, finished));
+        SharedWorker(Rc::new(RefCell::new(Worker {
+            jobs,
+            done,
+            running: vec![],
+            hint: None,
+            analysis: None,
+            fresh: false,
+        })))
+    }
+}
+
+impl SharedWorker {
+    fn start(&self, job: Job, doing: &'static str) {
+        let mut worker = self.0.borrow_mut();
+        if worker.jobs.send(job).is_ok() {
+            worker.running.push(doing);
+        }
+    }
+    /// Takes in the jobs finished since the last call.
+    fn poll(&self) {
+        let mut worker = self.0.borrow_mut();
+        while let Ok(done) = worker.done.try_recv() {
+            worker.running.remove(0);
+            worker.fresh = true;
+            match done {
+                Done::Hint(moves, heat) => worker.hint = Some((moves, heat)),
+                Done::Analysis(moves, reviews) => worker.analysis = Some((moves, reviews)),
+            }
+        }
+    }
+    /// What the worker is doing, if anything.
+    fn status(&self) -> Option<String> {
+        self.0
+            .borrow()
+            .running
+            .first()
+            .map(|&doing| doing.to_owned())
+    }
+    /// Puts the finished hint and analysis on `board` if they are about its game, returns
+    /// the summary of the analysis. With `take` they are used up.
+    fn apply(&self, board: &mut Board, take: bool) -> Option<String> {
+        let mut worker = self.0.borrow_mut();
+        let (hint, analysis) = if take {
+            (worker.hint.take(), worker.analysis.take())
+        } else {
+            (worker.hint.clone(), worker.analysis.clone())
+        };
+        if let Some((moves, heat)) = hint {
+            if &moves == board.moves() {
+                board.heat = heat;
+            }
+        }
+        let (moves, reviews) = analysis?;
+        if !moves.starts_with(board.moves()) && !board.moves().starts_with(&moves) {
+            return None;
+        }
+        annotate(board, &reviews);
+        Some(summary(&reviews))
+    }
+}
+
+impl fmt::Debug for SharedWorker {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "SharedWorker({:?})", self.0.borrow().running)
+    }
+}
+
+impl PartialEq for SharedWorker {
+    /// A job finished since the last build makes the data differ from its built copy,
+    /// so the GUI is built again and shows the result.
+    fn eq(&self, other: &SharedWorker) -> bool {
+        self.poll();
+        Rc::ptr_eq(&self.0, &other.0) && !self.0.borrow().fresh
+    }
 }
 
 impl GuiBuilder for GameData {
     fn build(&self) {
+        self.worker.poll();
+        self.worker.0.borrow_mut().fresh = false;
         match self.state.clone() {
             GameState::MainMenu => {
                 self.main_menu_gui();
@@ -36,6 +274,9 @@ impl GuiBuilder for GameData {
             GameState::LoadSaved => {
                 self.load_saved_gui(self.save_id);
             }
+            GameState::EnterCode => {
+                self.code_gui();
+            }
             GameState::Finished(r) => {
                 self.game_gui(r);
             }
@@ -50,22 +291,16 @@ impl GameData {
             row_heights: GuiDimension::relative_array(vec![0.9, 0.1]),
             ..Default::default()
         } << {
-            let mut lines: Vec<String> = Default::default();
-
-            if let Ok(file) = File::open("data/games.txt") {
-                lines = io::BufReader::new(file)
-                    .lines()
-                    .map(|r| r.unwrap_or_default())
-                    .collect();
-            }
+            let games = read_saves(&self.dirs.saves_path()).unwrap_or_default();
 
-            if !lines.is_empty() {
+            if !games.is_empty() {
                 let mut board = Board::default();
-                if 2 * id + 1 >= lines.len() {
+                if id >= games.len() {
                     id = 0;
                 }
 
-                board.load_from_string(&lines[2 * id + 1]);
+                let (header, moves) = &games[id];
+                let loaded = board.load_from_string(moves);
                 -GridLayout {
                     col_widths: GuiDimension::relative_array(vec![0.2, 1.0, 0.2]),
                     ..Default::default()
@@ -74,12 +309,15 @@ impl GameData {
                         << if id > 0 {
                             -Button {
                                 background: ButtonBckg::Image(
-                                    "images/left".to_owned(),
+                                    self.dirs.image("left"),
                                     Vec4::WHITE.with_w(0.3),
                                     Vec4::WHITE.with_w(0.4),
                                     Vec4::WHITE.with_w(0.25),
                                 ),
-                                callback: self.make_callback1(|data| data.save_id -= 1),
+                                callback: self.make_callback1(|data| {
+                                    data.save_id -= 1;
+                                    data.notice = None;
+                                }),
                                 ..Default::default()
                             };
                         };
@@ -88,46 +326,116 @@ impl GameData {
                         ..Default::default()
                     } << {
                         -Text {
-                            text: lines[2 * id].clone(),
+                            text: match (&self.notice, &loaded) {
+                                (Some(notice), _) => format!("{} - {}", header.trim(), notice),
+                                (None, Err(e)) => {
+                                    format!("{} - broken save: {}", header.trim(), e)
+                                }
+                                (None, Ok(())) => match board.players() {
+                                    Some((black, white)) => {
+                                        format!("{} - {} vs {}", header.trim(), black, white)
+                                    }
+                                    None => header.clone(),
+                                },
+                            },
                             color: Vec4::WHITE,
                             ..Default::default()
                         };
                         -Square::default() << self.board_gui(board.clone(), GameResult::Draw);
                     };
                     -Padding::ratios(0.1, 0.0, 0.3, 0.2)
-                        << if (id + 1) * 2 < lines.len() {
+                        << if id + 1 < games.len() {
                             -Button {
                                 background: ButtonBckg::Image(
-                                    "images/right".to_owned(),
+                                    self.dirs.image("right"),
                                     Vec4::WHITE.with_w(0.3),
                                     Vec4::WHITE.with_w(0.4),
                                     Vec4::WHITE.with_w(0.2),
                                 ),
-                                callback: self.make_callback1(|data| data.save_id += 1),
+                                callback: self.make_callback1(|data| {
+                                    data.save_id += 1;
+                                    data.notice = None;
+                                }),
                                 ..Default::default()
                             };
                         };
                 };
+                let replay_board = board.clone();
+                let analyzed_board = board.clone();
                 -GridLayout {
-                    col_widths: GuiDimension::relative_array(vec![0.5, 0.5]),
+                    col_widths: GuiDimension::relative_array(vec![0.5, 0.5, 0.5, 0.5]),
                     ..Default::default()
                 } << {
                     self.button(
                         "Main menu",
                         self.make_callback1(|data| {
                             data.state = GameState::MainMenu;
+                            data.notice = None;
                         }),
                         0.5,
                     );
-                    self.button(
-                        "Play",
-                        self.make_callback1(move |data| {
-                            data.state = GameState::Playing;
-                            data.board = board.clone();
-                            ai_new_game(data.board.moves());
-                        }),
-                        0.5,
-                    );
+                    if loaded.is_err() {
+                        -Padding::default();
+                        -Padding::default();
+                        -Padding::default();
+                    } else {
+                        self.button(
+                            "Export GIF",
+                            self.make_callback1(move |data| {
+                                let path = data
+                                    .dirs
+                                    .data
+                                    .join("replays")
+                                    .join(format!("game_{:03}.gif", id));
+                                let exported = fs::create_dir_all(path.parent().unwrap())
+                                    .and_then(|_| Art::load(&data.dirs.images_dir()))
+                                    .and_then(|art| {
+                                        save_gif(
+                                            &replay_board,
+                                            &art,
+                                            &ReplayOptions::default(),
+                                            &path,
+                                        )
+                                    });
+                                data.notice = Some(match exported {
+                                    Ok(()) => format!("Saved {}", path.display()),
+                                    Err(e) => format!("Export failed: {}", e),
+                                });
+                            }),
+                            0.5,
+                        );
+                        self.button(
+                            "Analyze",
+                            self.make_callback1(move |data| {
+                                let board = analyzed_board.clone();
+                                data.worker
+                                    .start(Job::Analyze(board.clone()), "Analyzing the game...");
+                                let result = board.result();
+                                data.resumable = !result.over();
+                                data.state = if result.over() {
+                                    GameState::Finished(result)
+                                } else {
+                                    GameState::Playing
+                                };
+                                data.board = board;
+                                data.notice = None;
+                                data.autosave();
+                            }),
+                            0.5,
+                        );
+                        self.button(
+                            "Play",
+                            self.make_callback1(move |data| {
+                                data.state = GameState::Playing;
+                                data.restore_ais(&board);
+                                data.board = board.clone();
+                                data.notice = None;
+                                data.resumable = !data.board.result().over();
+                                data.autosave();
+                            }),
+                            0.5,
+                        );
+                    }
                 }
             } else {
                 -Text {
@@ -150,8 +458,15 @@ impl GameData {
             .human_comes(self.intelligence.0, self.intelligence.1)
     }
     fn game_gui(&self, result: GameResult) {
+        let mut board = self.board.clone();
+        let summary = self.worker.apply(&mut board, false);
+        let notice = self
+            .notice
+            .clone()
+            .or(summary)
+            .or_else(|| self.worker.status());
         -OuterImage {
-            name: "images/wood".to_owned(),
+            name: self.dirs.image("wood"),
             mid: Vec2::new(0.5, 0.5),
             ..Default::default()
         } << -FixedPanel {
@@ -159,12 +474,29 @@ impl GameData {
             size: GuiDimension::Units(160.0),
             ..Default::default()
         } << {
-            self.sidebar_gui(self.board.can_undo(), self.board.can_redo(), result.clone());
-            self.board_gui(self.board.clone(), result);
+            self.sidebar_gui(
+                board.can_undo(),
+                board.can_redo(),
+                result.clone(),
+                notice,
+                board.moves().len(),
+                board.current_annotation().cloned(),
+                board.heat.iter().flatten().any(|&heat| heat > 0.0),
+            );
+            self.board_gui(board, result);
         };
     }
 
-    fn sidebar_gui(&self, can_undo: bool, can_redo: bool, result: GameResult) {
+    fn sidebar_gui(
+        &self,
+        can_undo: bool,
+        can_redo: bool,
+        result: GameResult,
+        notice: Option<String>,
+        move_count: usize,
+        note: Option<Annotation>,
+        hint_shown: bool,
+    ) {
         let mut title = "Playing".to_owned();
 
         match result {
@@ -176,7 +508,7 @@ impl GameData {
 
         -GridLayout {
             row_heights: GuiDimension::relative_array(vec![
-                0.1, 0.15, 0.15, 0.15, 0.4, 0.15, 0.15, 0.15,
+                0.1, 0.15, 0.15, 0.15, 0.4, 0.3, 0.15, 0.15, 0.15, 0.15, 0.15,
             ]),
             ..Default::default()
         } << {
@@ -188,21 +520,21 @@ impl GameData {
             if can_undo {
                 self.button(
                     "undo",
-                    self.make_callback1(|data| {
+                    self.game_callback(|data| {
                         data.board.undo();
-                        ai_undo();
                         data.state = GameState::Playing;
+                        data.autosave();
                     }),
                     0.5,
                 );
                 self.button(
                     "undo all",
-                    self.make_callback1(|data| {
+                    self.game_callback(|data| {
                         for _ in 0..data.board.moves().len() {
                             data.board.undo();
-                            ai_undo();
                         }
                         data.state = GameState::Playing;
+                        data.autosave();
                     }),
                     0.5,
                 );
@@ -214,13 +546,13 @@ impl GameData {
             if can_redo {
                 self.button(
                     "redo",
-                    self.make_callback1(|data| {
-                        if let Some(p) = data.board.redo_step() {
+                    self.game_callback(|data| {
+                        if data.board.redo_step().is_some() {
                             let r = data.board.redo();
                             if r != GameResult::NotFinished {
                                 data.state = GameState::Finished(r);
                             }
-                            ai_redo(p);
+                            data.autosave();
                         }
                     }),
                     0.5,
@@ -228,21 +560,42 @@ impl GameData {
             } else {
                 -Padding::default();
             }
-            -Padding::default();
+            let note_text = note.as_ref().map(|note| {
+                let symbol = note.symbol.map(MoveSymbol::as_str).unwrap_or_default();
+                format!("{}{} {}", move_count, symbol, note.comment)
+            });
+            if let Some(text) = notice.or(note_text) {
+                -Overlay::from(Vec4::WHITE.with_w(0.6))
+                    << -Text {
+                        text,
+                        ..Default::default()
+                    };
+            } else {
+                -Padding::default();
+            }
+            if move_count > 0 {
+                self.annotation_gui(move_count, note.unwrap_or_default());
+            } else {
+                -Padding::default();
+            }
             if result.over() {
+                self.button("New Game", self.game_callback(|data| data.new_game()), 0.5);
+            } else {
+                -Padding::default();
+            }
+            if hint_shown {
                 self.button(
-                    "New Game",
-                    self.make_callback1(|data| {
-                        data.state = GameState::Playing;
-                        data.board = Board::default();
-                        if data.intelligence.0 == PlayerInt::AI
-                            || data.intelligence.1 == PlayerInt::AI
-                        {
-                            ai_new_game(data.board.moves());
-                            if !data.human_comes() {
-                                ai_move(&mut data.board);
-                            }
-                        }
+                    "Hide hint",
+                    self.game_callback(|data| {
+                        data.board.heat = Default::default();
+                    }),
+                    0.5,
+                );
+            } else if !result.over() {
+                self.button(
+                    "Hint",
+                    self.game_callback(|data| {
+                        data.show_hint();
                     }),
                     0.5,
                 );
@@ -251,16 +604,110 @@ impl GameData {
             }
             self.button(
                 "Save",
-                self.make_callback1(|data| {
-                    data.board.append_game_to_saves();
+                self.game_callback(|data| {
+                    let mut board = data.board.clone();
+                    let player = |int: PlayerInt, side: usize| match int {
+                        PlayerInt::AI => format!(
+                            "AI ({}, {})",
+                            data.levels[side].name, data.profiles[side].name
+                        ),
+                        _ => int.to_string(),
+                    };
+                    board.set_players(
+                        player(data.intelligence.0, 0),
+                        player(data.intelligence.1, 1),
+                    );
+                    board.set_ais(data.ais());
+                    data.notice = Some(match board.append_game_to_saves(&data.dirs.saves_path()) {
+                        Ok(()) => "Game saved".to_owned(),
+                        Err(e) => format!("Save failed: {}", e),
+                    });
+                }),
+                0.5,
+            );
+            self.button(
+                "Copy code",
+                self.game_callback(|data| {
+                    data.notice = Some(match copy_to_clipboard(data.board.to_code()) {
+                        Ok(()) => "Code copied".to_owned(),
+                        Err(e) => e,
+                    });
                 }),
                 0.5,
             );
             self.button(
                 "Main menu",
-                self.make_callback1(|data| {
+                self.game_callback(|data| {
                     data.state = GameState::MainMenu;
                     data.board = Board::default();
+                    data.notice = None;
+                }),
+                0.5,
+            );
+        }
+    }
+
+    /// Buttons editing the notes of the `id`-th move.
+    fn annotation_gui(&self, id: usize, note: Annotation) {
+        -GridLayout {
+            col_widths: GuiDimension::relative_array(vec![1.0, 1.0]),
+            row_heights: GuiDimension::relative_array(vec![1.0, 1.0]),
+            ..Default::default()
+        } << {
+            let symbol = note.symbol.map(MoveSymbol::as_str).unwrap_or("-");
+            let n = note.clone();
+            self.button(
+                &format!("symbol {}", symbol),
+                self.game_callback(move |data| {
+                    let mut note = n.clone();
+                    note.symbol = MoveSymbol::cycle(note.symbol);
+                    data.board.annotate(id, note);
+                    data.autosave();
+                }),
+                0.5,
+            );
+            let n = note.clone();
+            self.button(
+                "paste comment",
+                self.game_callback(move |data| match paste_from_clipboard() {
+                    Ok(comment) => {
+                        let mut note = n.clone();
+                        note.comment = comment.trim().to_owned();
+                        data.board.annotate(id, note);
+                        data.notice = None;
+                        data.autosave();
+                    }
+                    Err(e) => data.notice = Some(e),
+                }),
+                0.5,
+            );
+            let n = note.clone();
+            self.button(
+                "paste marks",
+                self.game_callback(move |data| {
+                    let marks = paste_from_clipboard().and_then(|text| {
+                        text.split_whitespace()
+                            .map(|m| Mark::parse(m).ok_or_else(|| format!("Invalid mark: {}", m)))
+                            .collect::<Result<Vec<Mark>, String>>()
+                    });
+                    match marks {
+                        Ok(marks) => {
+                            let mut note = n.clone();
+                            note.marks = marks;
+                            data.board.annotate(id, note);
+                            data.notice = None;
+                            data.autosave();
+                        }
+                        Err(e) => data.notice = Some(e),
+                    }
+                }),
+                0.5,
+            );
+            self.button(
+                "clear note",
+                self.game_callback(move |data| {
+                    data.board.annotate(id, Annotation::default());
+                    data.autosave();
                 }),
                 0.5,
             );
@@ -276,8 +723,17 @@ impl GameData {
     }
 
     fn main_menu_gui(&self) {
+        let resume = self.resume.clone();
+        let mut row_heights = vec![0.5, 0.5, 0.5, 1.0, 0.5, 0.5, 1.0, 0.2];
+        if resume.is_some() {
+            row_heights.insert(0, 0.5);
+        }
+        if self.notice.is_some() {
+            row_heights.insert(0, 0.5);
+        }
+
         -OuterImage {
-            name: "images/main_menu".to_owned(),
+            name: self.dirs.image("main_menu"),
             mid: Vec2::new_xy(0.5),
             ..Default::default()
         };
@@ -285,30 +741,42 @@ impl GameData {
             << -Padding::relative_x(1.0 / 5.0)
             << -Overlay::from(Vec4::WHITE.with_w(0.2))
             << -GridLayout {
-                row_heights: GuiDimension::relative_array(vec![0.5, 0.5, 1.0, 1.0, 0.2]),
+                row_heights: GuiDimension::relative_array(row_heights),
                 ..Default::default()
             }
             << {
+                if let Some(notice) = self.notice.clone() {
+                    -Overlay::from(Vec4::WHITE.with_w(0.5))
+                        << -Text {
+                            text: notice,
+                            ..Default::default()
+                        };
+                }
+                if let Some((board, players)) = resume {
+                    self.button(
+                        "Continue last game",
+                        self.make_callback1(move |data| {
+                            data.intelligence = players;
+                            data.restore_ais(&board);
+                            data.start_from(board.clone());
+                        }),
+                        0.8,
+                    );
+                }
+                self.button("New Game", self.make_callback1(|data| data.new_game()), 0.8);
                 self.button(
-                    "New Game",
+                    "Load Game",
                     self.make_callback1(|data| {
-                        data.state = GameState::Playing;
-                        data.board = Board::default();
-                        if data.intelligence.0 == PlayerInt::AI
-                            || data.intelligence.1 == PlayerInt::AI
-                        {
-                            ai_new_game(data.board.moves());
-                            if !data.human_comes() {
-                                ai_move(&mut data.board);
-                            }
-                        }
+                        data.state = GameState::LoadSaved;
+                        data.notice = None;
                     }),
                     0.8,
                 );
                 self.button(
-                    "Load Game",
+                    "Load from code",
                     self.make_callback1(|data| {
-                        data.state = GameState::LoadSaved;
+                        data.state = GameState::EnterCode;
+                        data.notice = None;
                     }),
                     0.8,
                 );
@@ -324,22 +792,58 @@ impl GameData {
                     self.button(
                         &self.int_text(),
                         self.make_callback1(|data| {
-                            data.intelligence = match data.intelligence {
-                                (PlayerInt::Human, PlayerInt::Human) => {
-                                    (PlayerInt::AI, PlayerInt::Human)
-                                }
-                                (PlayerInt::AI, PlayerInt::Human) => {
-                                    (PlayerInt::Human, PlayerInt::AI)
-                                }
-                                (PlayerInt::Human, PlayerInt::AI) => {
-                                    (PlayerInt::Human, PlayerInt::Human)
-                                }
-                                _ => unimplemented!(),
-                            };
+                            data.intelligence = next_players(data.intelligence);
                         }),
                         0.8,
                     );
                 };
+                // The buttons of black, then of white, like the players.
+                -GridLayout {
+                    col_widths: GuiDimension::relative_array(vec![1.0, 0.5, 0.5]),
+                    ..Default::default()
+                } << {
+                    -Overlay::from(Vec4::WHITE.with_w(0.5))
+                        << -Text {
+                            text: "Level:".to_owned(),
+                            ..Default::default()
+                        };
+                    for side in 0..2 {
+                        self.button(
+                            self.levels[side].name,
+                            self.make_callback1(move |data| {
+                                let level = &mut data.levels[side];
+                                let current = LEVELS.iter().position(|l| l == level);
+                                *level = LEVELS[current.map_or(0, |i| (i + 1) % LEVELS.len())];
+                            }),
+                            0.8,
+                        );
+                    }
+                };
+                -GridLayout {
+                    col_widths: GuiDimension::relative_array(vec![1.0, 0.5, 0.5]),
+                    ..Default::default()
+                } << {
+                    -Overlay::from(Vec4::WHITE.with_w(0.5))
+                        << -Text {
+                            text: "Style:".to_owned(),
+                            ..Default::default()
+                        };
+                    for side in 0..2 {
+                        self.button(
+                            &self.profiles[side].name,
+                            self.make_callback1(move |data| {
+                                let (profiles, errors) = load_profiles(&data.dirs.profiles_dir());
+                                let profile = &mut data.profiles[side];
+                                let current = profiles.iter().position(|p| p.name == profile.name);
+                                *profile = profiles
+                                    [current.map_or(0, |i| (i + 1) % profiles.len())]
+                                .clone();
+                                data.notice = errors.into_iter().next();
+                            }),
+                            0.8,
+                        );
+                    }
+                };
                 self.button(
                     "Exit",
                     self.make_callback3(|_data, _button, world| {
@@ -354,6 +858,96 @@ impl GameData {
             };
     }
 
+    /// Typing a code on the buttons of its characters, or pasting one.
+    fn code_gui(&self) {
+        -OuterImage {
+            name: self.dirs.image("main_menu"),
+            mid: Vec2::new_xy(0.5),
+            ..Default::default()
+        };
+        -Square::default()
+            << -Padding::relative_x(1.0 / 8.0)
+            << -Overlay::from(Vec4::WHITE.with_w(0.2))
+            << -GridLayout {
+                row_heights: GuiDimension::relative_array(vec![0.6, 2.4, 0.6]),
+                ..Default::default()
+            }
+            << {
+                -Overlay::from(Vec4::WHITE.with_w(0.5))
+                    << -Text {
+                        text: match (&self.notice, self.code.is_empty()) {
+                            (Some(notice), _) => format!("{} - {}", self.code, notice),
+                            (None, true) => "Type or paste a code".to_owned(),
+                            (None, false) => self.code.clone(),
+                        },
+                        ..Default::default()
+                    };
+                -GridLayout {
+                    col_widths: GuiDimension::relative_array(vec![1.0; 8]),
+                    row_heights: GuiDimension::relative_array(vec![1.0; 8]),
+                    ..Default::default()
+                } << {
+                    for &c in ALPHABET.iter() {
+                        let c = c as char;
+                        -Padding::absolute(2.0)
+                            << -Button {
+                                callback: self.make_callback1(move |data| {
+                                    data.code.push(c);
+                                    data.notice = None;
+                                }),
+                                text: c.to_string(),
+                                text_color: Vec4::BLACK,
+                                background: ButtonBckg::Fill(Vec4::WHITE.with_w(0.5)),
+                                ..Default::default()
+                            };
+                    }
+                };
+                -GridLayout {
+                    col_widths: GuiDimension::relative_array(vec![1.0; 4]),
+                    ..Default::default()
+                } << {
+                    self.button(
+                        "Paste",
+                        self.make_callback1(|data| match paste_from_clipboard() {
+                            Ok(code) => {
+                                data.code = code.trim().to_owned();
+                                data.notice = None;
+                            }
+                            Err(e) => data.notice = Some(e),
+                        }),
+                        0.8,
+                    );
+                    self.button(
+                        "Delete",
+                        self.make_callback1(|data| {
+                            data.code.pop();
+                            data.notice = None;
+                        }),
+                        0.8,
+                    );
+                    self.button(
+                        "Load",
+                        self.make_callback1(|data| match Board::from_code(&data.code) {
+                            Ok(board) => {
+                                data.code.clear();
+                                data.start_from(board);
+                            }
+                            Err(e) => data.notice = Some(e),
+                        }),
+                        0.8,
+                    );
+                    self.button(
+                        "Main menu",
+                        self.make_callback1(|data| {
+                            data.state = GameState::MainMenu;
+                            data.notice = None;
+                        }),
+                        0.8,
+                    );
+                };
+            };
+    }
+
     fn button(&self, text: &str, cb: GuiCallback<Button>, alpha: f32) {
         -Padding::default()
             << -Button {
@@ -372,12 +966,12 @@ impl GameData {
         -Square::default() << -Padding::absolute(24.0) << {
             -Padding::ratios(0.05, 0.0, 0.05, 0.0)
                 << -Image {
-                    name: "images/board_shadow".to_owned(),
+                    name: self.dirs.image("board_shadow"),
                     ..Default::default()
                 };
 
             -Padding::relative(0.05)
-                << -Image::from("images/board")
+                << -Image::from(self.dirs.image("board").as_str())
                 << -Padding::relative(1.0 / 32.0)
                 << -GridLayout {
                     col_widths: GuiDimension::relative_array(vec![1.0; MAP_SIZE]),
@@ -389,14 +983,28 @@ impl GameData {
                     let over = result.over();
                     let move_count = board.moves().len();
                     let map = board.move_to_id_map();
+                    let marks = board
+                        .current_annotation()
+                        .map(|note| mark_glyphs(&note.marks))
+                        .unwrap_or_default();
 
                     for n in 0..MAP_SIZE {
                         for k in 0..MAP_SIZE {
                             let cell = board.cell(n, k);
                             let heat = board.heat[n][k];
+                            let mark = marks.get(&(n, k)).cloned();
 
                             if cell == Cell::Empty {
-                                self.cell_gui(cell, (n, k), false, 0, black_turn, heat, active);
+                                self.cell_gui(
+                                    cell,
+                                    (n, k),
+                                    false,
+                                    0,
+                                    black_turn,
+                                    heat,
+                                    active,
+                                    mark,
+                                );
                             } else {
                                 let id = *map.get(&(n, k)).unwrap();
                                 let mut highlighted = id == move_count || id + 1 == move_count;
@@ -423,6 +1031,7 @@ impl GameData {
                                     black_turn,
                                     heat,
                                     active,
+                                    mark,
                                 );
                             }
                         }
@@ -440,6 +1049,7 @@ impl GameData {
         black_turn: bool,
         heat: f32,
         active: bool,
+        mark: Option<String>,
     ) {
         -Padding::absolute(2.0) << {
             -Overlay {
@@ -450,27 +1060,25 @@ impl GameData {
                 Cell::Empty if active => {
                     -Padding::relative(0.03)
                         << -Button {
-                            callback: self.make_callback1(move |data| {
+                            callback: self.game_callback(move |data| {
+                                data.notice = None;
+                                data.resumable = true;
                                 let res = data.board.put(p.0, p.1);
                                 if res != GameResult::NotFinished {
                                     data.game_finished(res);
                                 }
                                 if GameState::Playing == data.state {
                                     if !data.human_comes() {
-                                        let res = ai_move(&mut data.board);
+                                        let res = data.ai_move();
                                         if res != GameResult::NotFinished {
                                             data.game_finished(res);
                                         }
                                     }
                                 }
+                                data.autosave();
                             }),
                             background: ButtonBckg::Image(
-                                if black_turn {
-                                    "images/black"
-                                } else {
-                                    "images/white"
-                                }
-                                .to_owned(),
+                                self.dirs.image(if black_turn { "black" } else { "white" }),
                                 Vec4::new(1.0, 1.0, 1.0, 0.0),
                                 Vec4::new(1.0, 1.0, 1.0, 0.5),
                                 Vec4::new(1.0, 1.0, 1.0, 0.9),
@@ -481,9 +1089,9 @@ impl GameData {
                 Cell::White => {
                     self.cell_img(
                         if highlight {
-                            "images/white_highlighted"
+                            "white_highlighted"
                         } else {
-                            "images/white"
+                            "white"
                         },
                         Vec4::BLACK,
                         n,
@@ -493,9 +1101,9 @@ impl GameData {
                 Cell::Black => {
                     self.cell_img(
                         if highlight {
-                            "images/black_highlighted"
+                            "black_highlighted"
                         } else {
-                            "images/black"
+                            "black"
                         },
                         Vec4::WHITE,
                         n,
@@ -504,18 +1112,26 @@ impl GameData {
                 }
                 _ => {}
             }
+            if let Some(mark) = mark {
+                -Text {
+                    text: mark,
+                    color: Vec4::new(0.8, 0.05, 0.15, 1.0),
+                    font_size: FontSize::relative_steps(0.7, (8.0, 40.0), 4.0),
+                    ..Default::default()
+                };
+            }
         };
     }
 
     fn cell_img(&self, name: &str, clr: Vec4, n: usize, show_number: bool) {
         -Padding::ratios(0.03, 0.0, 0.03, 0.0)
             << -Image {
-                name: "images/stone_shadow".to_owned(),
+                name: self.dirs.image("stone_shadow"),
                 size: WidgetSize::relative(Vec2::new(1.1, 1.1)),
                 ..Default::default()
             };
         -Padding::relative(0.03)
-            << -Image::from(name)
+            << -Image::from(self.dirs.image(name).as_str())
             << if show_number {
                 -Text {
                     text: format!("{}", n),
@@ -529,6 +1145,177 @@ impl GameData {
     fn game_finished(&mut self, result: GameResult) {
         self.state = GameState::Finished(result);
     }
+
+    fn new_game(&mut self) {
+        self.state = GameState::Playing;
+        self.board = Board::default();
+        self.notice = None;
+        self.resumable = true;
+        self.engine.get().set_seed(rand::random());
+        if !self.human_comes() {
+            self.ai_move();
+        }
+        self.autosave();
+    }
+
+    fn start_from(&mut self, board: Board) {
+        self.board = board;
+        self.notice = None;
+        self.state = GameState::Playing;
+        self.engine.get().set_seed(rand::random());
+
+        let res = self.board.result();
+        self.resumable = !res.over();
+        if res != GameResult::NotFinished {
+            self.game_finished(res);
+        } else if !self.human_comes() {
+            let res = self.ai_move();
+            if res != GameResult::NotFinished {
+                self.game_finished(res);
+            }
+        }
+        self.autosave();
+    }
+
+    /// Lets the AI play the next move, at the level and in the style chosen for its side in the main menu,
+    /// with tree search if the player to move is MCTS. How it chose the move is shown in the sidebar.
+    fn ai_move(&mut self) -> GameResult {
+        let side = self.board.moves().len() % 2;
+        let player = [self.intelligence.0, self.intelligence.1][side];
+        let choice = {
+            let mut engine = self.engine.get();
+            engine.set_method(match player {
+                PlayerInt::Mcts => Method::Mcts(MctsOptions::for_level(&self.levels[side])),
+                _ => Method::AlphaBeta,
+            });
+            engine.set_level(self.levels[side]);
+            engine.set_profile(&self.profiles[side]);
+            engine.sync(&self.board);
+            engine.best_move()
+        };
+        self.notice = Some(format!("AI played {}", choice));
+        self.board.put(choice.pos.0, choice.pos.1)
+    }
+
+    /// Starts coloring the intersections by how much the AI likes playing there, in the style
+    /// chosen in the main menu for the player to move. The colors show up once the worker is done.
+    fn show_hint(&mut self) {
+        let side = self.board.moves().len() % 2;
+        let job = Job::Hint(self.board.clone(), self.profiles[side].clone());
+        self.worker.start(job, "Looking for a hint...");
+    }
+
+    /// Takes the hint and the analysis the worker finished into the game, if they are still about it.
+    fn collect(&mut self) {
+        self.worker.poll();
+        if let Some(summary) = self.worker.apply(&mut self.board, true) {
+            self.notice = Some(summary);
+            self.autosave();
+        }
+    }
+
+    /// A callback of the game screen, which sees the results of the worker like the screen does.
+    fn game_callback<F: Fn(&mut GameData) + 'static>(&self, f: F) -> GuiCallback<Button> {
+        self.make_callback1(move |data| {
+            data.collect();
+            f(data);
+        })
+    }
+
+    /// The level and style of each AI player, as stored with the game.
+    fn ais(&self) -> [Option<AiSetup>; 2] {
+        let mut ais = [None, None];
+        for (side, int) in [self.intelligence.0, self.intelligence.1]
+            .iter()
+            .enumerate()
+        {
+            if *int != PlayerInt::Human {
+                ais[side] = Some(AiSetup {
+                    level: self.levels[side].name.to_owned(),
+                    style: self.profiles[side].name.clone(),
+                });
+            }
+        }
+        ais
+    }
+
+    /// Picks the level and style the AI players of `board` had, where they still exist.
+    fn restore_ais(&mut self, board: &Board) {
+        let (profiles, _) = load_profiles(&self.dirs.profiles_dir());
+        for (side, ai) in board.ais().iter().enumerate() {
+            if let Some(ai) = ai {
+                if let Some(level) = Level::by_name(&ai.level) {
+                    self.levels[side] = level;
+                }
+                if let Some(profile) = profiles.iter().find(|p| p.name == ai.style) {
+                    self.profiles[side] = profile.clone();
+                }
+            }
+        }
+    }
+
+    /// Keeps the game in the resume slot while it is played, and empties the slot once it ends.
+    fn autosave(&mut self) {
+        if !self.resumable {
+            return;
+        }
+        self.board.set_ais(self.ais());
+        let saved = if self.board.result().over() {
+            self.resume = None;
+            Board::clear_resume(&self.dirs.resume_path())
+        } else {
+            self.resume = Some((self.board.clone(), self.intelligence));
+            self.board
+                .save_resume(&self.dirs.resume_path(), self.intelligence)
+        };
+        if let Err(e) = saved {
+            self.notice = Some(format!("Autosave failed: {}", e));
+        }
+    }
+}
+
+/// The text drawn on the intersections marked by `marks`, arrows are shown at their end.
+fn mark_glyphs(marks: &[Mark]) -> HashMap<(usize, usize), String> {
+    marks
+        .iter()
+        .map(|mark| match mark {
+            Mark::Triangle(p) => (*p, "\u{25b2}".to_owned()),
+            Mark::Square(p) => (*p, "\u{25a0}".to_owned()),
+            Mark::Circle(p) => (*p, "\u{25cb}".to_owned()),
+            Mark::Letter(p, text) => (*p, text.clone()),
+            Mark::Arrow(from, to) => {
+                let dir = (
+                    (to.0 as i32 - from.0 as i32).signum(),
+                    (to.1 as i32 - from.1 as i32).signum(),
+                );
+                let arrow = match dir {
+                    (-1, -1) => "\u{2196}",
+                    (-1, 0) => "\u{2191}",
+                    (-1, 1) => "\u{2197}",
+                    (0, -1) => "\u{2190}",
+                    (1, -1) => "\u{2199}",
+                    (1, 0) => "\u{2193}",
+                    (1, 1) => "\u{2198}",
+                    _ => "\u{2192}",
+                };
+                (*to, arrow.to_owned())
+            }
+        })
+        .collect()
+}
+
+fn copy_to_clipboard(text: String) -> Result<(), String> {
+    let mut ctx: ClipboardContext =
+        ClipboardProvider::new().map_err(|e| format!("No clipboard: {}", e))?;
+    ctx.set_contents(text)
+        .map_err(|e| format!("Copy failed: {}", e))
+}
+
+fn paste_from_clipboard() -> Result<String, String> {
+    let mut ctx: ClipboardContext =
+        ClipboardProvider::new().map_err(|e| format!("No clipboard: {}", e))?;
+    ctx.get_contents()
+        .map_err(|e| format!("Paste failed: {}", e))
 }
 
 #[derive(Default)]